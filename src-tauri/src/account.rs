@@ -1 +1,8 @@
+pub mod account_management;
+pub mod account_management_types;
 pub mod account_reset_types;
+pub mod device_types;
+pub mod devices;
+pub mod logout_types;
+pub mod oauth_account;
+pub mod uiaa;
\ No newline at end of file
@@ -0,0 +1,333 @@
+use matrix_sdk::Client;
+use ruma::api::client::account::change_password;
+use ruma::thirdparty::Medium;
+use ruma::{ClientSecret, OwnedClientSecret, OwnedSessionId, UInt};
+use tauri::http::header;
+use tauri::{AppHandle, State};
+use tracing::debug;
+use crate::account::account_management_types::{ThirdPartyId, ThreepidValidation};
+use crate::account::oauth_account::{is_oauth, open_account_management};
+use crate::account::uiaa::password_auth;
+use crate::user::{end_session, wipe_account};
+use crate::ClientState;
+
+/// On OAuth servers the account is managed on the server's own page, open it and tell the caller to
+/// stop there.
+///
+/// ### Returns
+/// `true` if the account management page was opened.
+async fn redirect_if_oauth(client: &Client, app_handle: &AppHandle) -> Result<bool, String> {
+    if !is_oauth(client) {
+        return Ok(false);
+    }
+    open_account_management(client, app_handle)
+        .await
+        .map_err(|e| format!("Failed to open account management: {}", e))?;
+    Ok(true)
+}
+
+/// Change the account password. On OAuth servers this opens the account management page instead.
+///
+/// # Arguments
+/// * `new_password` - The password to change to.
+/// * `password` - The current password, needed for UIAA.
+/// * `logout_devices` - Whether to sign out every other device of the account.
+/// * `state` - The client state containing the Matrix client to change the password of.
+/// * `app_handle` - The app handle used to open the account management page.
+#[tauri::command]
+pub async fn change_password(
+    new_password: String,
+    password: Option<String>,
+    logout_devices: bool,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    if redirect_if_oauth(client, &app_handle).await? {
+        return Ok("account management opened".into());
+    }
+
+    let mut request = change_password::v3::Request::new(new_password);
+    request.logout_devices = logout_devices;
+    if let Err(e) = client.send(request.clone()).await {
+        let Some(uiaa_info) = e.as_uiaa_response() else {
+            return Err(format!("Failed to change password: {}", e));
+        };
+        debug!("UIAA required to change password");
+        let password = password.ok_or("Password required for UIAA authentication")?;
+        let user_id = client.user_id().ok_or("Not logged in")?;
+
+        request.auth = Some(password_auth(user_id, password, uiaa_info.session.clone()));
+        client
+            .send(request)
+            .await
+            .map_err(|e| format!("Failed to change password: {}", e))?;
+    }
+
+    Ok("password changed".into())
+}
+
+/// Deactivate the account for good, then delete everything stored for it on this device. On OAuth
+/// servers this opens the account management page instead.
+///
+/// # Arguments
+/// * `password` - The account password, needed for UIAA.
+/// * `erase` - Whether to also ask the server to erase the account's messages (GDPR erasure).
+/// * `state` - The client state containing the Matrix client to deactivate.
+/// * `app_handle` - The app handle used to open the account management page and clean up.
+#[tauri::command]
+pub async fn deactivate_account(
+    password: Option<String>,
+    erase: bool,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let user_id = {
+        let state_r = state.0.read().await;
+        let client_handler = state_r.as_ref().unwrap();
+        let client = client_handler.get_client();
+
+        if redirect_if_oauth(client, &app_handle).await? {
+            return Ok("account management opened".into());
+        }
+
+        let user_id = client.user_id().ok_or("Not logged in")?.to_owned();
+        let account = client.account();
+        if let Err(e) = account.deactivate(None, None, erase).await {
+            let Some(uiaa_info) = e.as_uiaa_response() else {
+                return Err(format!("Failed to deactivate account: {}", e));
+            };
+            debug!("UIAA required to deactivate account");
+            let password = password.ok_or("Password required for UIAA authentication")?;
+
+            account
+                .deactivate(None, Some(password_auth(&user_id, password, uiaa_info.session.clone())), erase)
+                .await
+                .map_err(|e| format!("Failed to deactivate account: {}", e))?;
+        }
+        user_id
+    };
+
+    // the session is gone with the account, so there is nothing to log out of on the server
+    drop(end_session(&state, &app_handle).await);
+    wipe_account(&app_handle, user_id.as_str())
+        .await
+        .map_err(|e| format!("Account deactivated, but failed to delete local data: {}", e))?;
+
+    Ok("account deactivated".into())
+}
+
+/// List the email addresses and phone numbers bound to the account.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client to look up.
+#[tauri::command]
+pub async fn get_3pids(
+    state: State<'_, ClientState>,
+) -> Result<Vec<ThirdPartyId>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let response = client_handler
+        .get_client()
+        .account()
+        .get_3pids()
+        .await
+        .map_err(|e| format!("Failed to get third-party identifiers: {}", e))?;
+
+    Ok(response
+        .threepids
+        .into_iter()
+        .map(|threepid| ThirdPartyId {
+            medium: threepid.medium.to_string(),
+            address: threepid.address,
+            validated_at: threepid.validated_at.get().into(),
+            added_at: threepid.added_at.get().into(),
+        })
+        .collect())
+}
+
+/// Ask the homeserver to send a validation email or SMS for a new third-party identifier. On OAuth
+/// servers this opens the account management page instead and returns nothing.
+///
+/// # Arguments
+/// * `medium` - Either `email` or `msisdn`.
+/// * `address` - The email address, or the phone number without its country code.
+/// * `country` - The two-letter country code of the phone number, for `msisdn`.
+/// * `send_attempt` - Starts at 1, increase it to send the email or SMS again.
+/// * `client_secret` - The client secret of the previous attempt when sending again, the
+///   homeserver only treats it as the same validation if the secret matches.
+/// * `state` - The client state containing the Matrix client to request the token with.
+/// * `app_handle` - The app handle used to open the account management page.
+#[tauri::command]
+pub async fn request_3pid_token(
+    medium: String,
+    address: String,
+    country: Option<String>,
+    send_attempt: u32,
+    client_secret: Option<String>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<Option<ThreepidValidation>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    if redirect_if_oauth(client, &app_handle).await? {
+        return Ok(None);
+    }
+
+    let client_secret = match client_secret {
+        Some(client_secret) => OwnedClientSecret::try_from(client_secret).map_err(|e| e.to_string())?,
+        None => ClientSecret::new(),
+    };
+    let send_attempt = UInt::from(send_attempt);
+    let account = client.account();
+    let (sid, submit_url) = match Medium::from(medium.as_str()) {
+        Medium::Email => {
+            let response = account
+                .request_3pid_email_token(&client_secret, &address, send_attempt)
+                .await
+                .map_err(|e| format!("Failed to send validation email: {}", e))?;
+            (response.sid, response.submit_url)
+        }
+        Medium::Msisdn => {
+            let country = country.ok_or("A country code is required for phone numbers")?;
+            let response = account
+                .request_3pid_msisdn_token(&client_secret, &country, &address, send_attempt)
+                .await
+                .map_err(|e| format!("Failed to send validation SMS: {}", e))?;
+            (response.sid, response.submit_url)
+        }
+        _ => return Err(format!("Unsupported medium: {}", medium)),
+    };
+
+    Ok(Some(ThreepidValidation {
+        client_secret: client_secret.to_string(),
+        sid: sid.to_string(),
+        submit_url,
+    }))
+}
+
+/// Submit the code sent by SMS for a phone number validation, after which it can be bound with
+/// [`add_3pid`].
+///
+/// # Arguments
+/// * `submit_url` - The submit URL returned by [`request_3pid_token`].
+/// * `client_secret` - The client secret returned by [`request_3pid_token`].
+/// * `sid` - The session id returned by [`request_3pid_token`].
+/// * `token` - The code from the SMS.
+/// * `state` - The client state containing the Matrix client to submit the code with.
+#[tauri::command]
+pub async fn submit_3pid_token(
+    submit_url: String,
+    client_secret: String,
+    sid: String,
+    token: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let http_client = {
+        let state_r = state.0.read().await;
+        let client_handler = state_r.as_ref().unwrap();
+        client_handler.get_client().http_client().clone()
+    };
+
+    let body = serde_json::json!({
+        "sid": sid,
+        "client_secret": client_secret,
+        "token": token,
+    });
+    let response = http_client
+        .post(&submit_url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| format!("Failed to submit validation code: {}", e))?;
+    let response: serde_json::Value = serde_json::from_slice(
+        &response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to submit validation code: {}", e))?,
+    )
+    .map_err(|e| format!("Failed to submit validation code: {}", e))?;
+
+    if response["success"].as_bool() != Some(true) {
+        return Err("The validation code is wrong".to_string());
+    }
+    Ok("validation code accepted".into())
+}
+
+/// Bind a validated third-party identifier to the account.
+///
+/// # Arguments
+/// * `client_secret` - The client secret returned by [`request_3pid_token`].
+/// * `sid` - The session id returned by [`request_3pid_token`].
+/// * `password` - The account password, needed for UIAA.
+/// * `state` - The client state containing the Matrix client to add the identifier to.
+#[tauri::command]
+pub async fn add_3pid(
+    client_secret: String,
+    sid: String,
+    password: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let client_secret = OwnedClientSecret::try_from(client_secret).map_err(|e| e.to_string())?;
+    let sid = OwnedSessionId::try_from(sid).map_err(|e| e.to_string())?;
+    let account = client.account();
+
+    if let Err(e) = account.add_3pid(&client_secret, &sid, None).await {
+        let Some(uiaa_info) = e.as_uiaa_response() else {
+            return Err(format!("Failed to add third-party identifier: {}", e));
+        };
+        debug!("UIAA required to add third-party identifier");
+        let password = password.ok_or("Password required for UIAA authentication")?;
+        let user_id = client.user_id().ok_or("Not logged in")?;
+
+        account
+            .add_3pid(&client_secret, &sid, Some(password_auth(user_id, password, uiaa_info.session.clone())))
+            .await
+            .map_err(|e| format!("Failed to add third-party identifier: {}", e))?;
+    }
+
+    Ok("third-party identifier added".into())
+}
+
+/// Unbind an email address or phone number from the account. On OAuth servers this opens the
+/// account management page instead.
+///
+/// # Arguments
+/// * `medium` - Either `email` or `msisdn`.
+/// * `address` - The address to remove, as returned by [`get_3pids`].
+/// * `state` - The client state containing the Matrix client to remove the identifier from.
+/// * `app_handle` - The app handle used to open the account management page.
+#[tauri::command]
+pub async fn delete_3pid(
+    medium: String,
+    address: String,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    if redirect_if_oauth(client, &app_handle).await? {
+        return Ok("account management opened".into());
+    }
+
+    client
+        .account()
+        .delete_3pid(&address, Medium::from(medium.as_str()), None)
+        .await
+        .map_err(|e| format!("Failed to remove third-party identifier: {}", e))?;
+
+    Ok("third-party identifier removed".into())
+}
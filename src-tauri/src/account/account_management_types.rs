@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+/// An email address or phone number bound to the account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThirdPartyId {
+    /// Either `email` or `msisdn`.
+    pub medium: String,
+    pub address: String,
+    /// Milliseconds since the unix epoch.
+    pub validated_at: u64,
+    /// Milliseconds since the unix epoch.
+    pub added_at: u64,
+}
+
+/// A pending validation of a third-party identifier, returned by `request_3pid_token` and passed
+/// back to `add_3pid` once the user followed the link or entered the code through
+/// `submit_3pid_token`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreepidValidation {
+    pub client_secret: String,
+    pub sid: String,
+    /// Where the code sent by SMS has to be submitted, if the homeserver validates it itself. Email
+    /// validations are completed by clicking the link in the email instead.
+    pub submit_url: Option<String>,
+}
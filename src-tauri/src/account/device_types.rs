@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// One of the account's devices (sessions), as listed in the session manager.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub display_name: Option<String>,
+    pub last_seen_ip: Option<String>,
+    /// Milliseconds since the unix epoch.
+    pub last_seen_ts: Option<u64>,
+    /// Whether the device is cross-signed by the account, i.e. verified.
+    pub is_verified: bool,
+    /// Whether this is the device Echelon is currently running as.
+    pub is_current: bool,
+}
@@ -0,0 +1,120 @@
+use ruma::OwnedDeviceId;
+use tauri::{AppHandle, State};
+use tracing::debug;
+use crate::account::device_types::DeviceInfo;
+use crate::account::oauth_account::{is_oauth, open_account_management};
+use crate::account::uiaa::password_auth;
+use crate::ClientState;
+
+/// The device display name used when the user doesn't pick one, e.g. "Echelon (linux)".
+pub fn default_device_name() -> String {
+    format!("Echelon ({})", std::env::consts::OS)
+}
+
+/// List all devices (sessions) of the account, with their verification state.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client to list the devices of.
+#[tauri::command]
+pub async fn get_devices(
+    state: State<'_, ClientState>,
+) -> Result<Vec<DeviceInfo>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let user_id = client.user_id().ok_or("Not logged in")?;
+
+    let response = client.devices().await.map_err(|e| e.to_string())?;
+    let mut devices = Vec::new();
+    for device in response.devices {
+        let is_verified = client
+            .encryption()
+            .get_device(user_id, &device.device_id)
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|d| d.is_verified());
+
+        devices.push(DeviceInfo {
+            is_current: client.device_id() == Some(&*device.device_id),
+            device_id: device.device_id.to_string(),
+            display_name: device.display_name,
+            last_seen_ip: device.last_seen_ip,
+            last_seen_ts: device.last_seen_ts.map(|ts| ts.get().into()),
+            is_verified,
+        });
+    }
+
+    Ok(devices)
+}
+
+/// Rename one of the account's devices, including the current one.
+///
+/// # Arguments
+/// * `device_id` - The device to rename.
+/// * `display_name` - The new display name, visible to other users.
+/// * `state` - The client state containing the Matrix client to rename the device with.
+#[tauri::command]
+pub async fn rename_device(
+    device_id: String,
+    display_name: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    client_handler
+        .get_client()
+        .rename_device(&OwnedDeviceId::from(device_id), &display_name)
+        .await
+        .map_err(|e| format!("Failed to rename device: {}", e))?;
+    Ok("device renamed".into())
+}
+
+/// Sign out (delete) other devices of the account. Needs the account password for UIAA, or on
+/// OAuth servers opens the account management page where the user can end the sessions instead.
+///
+/// # Arguments
+/// * `device_ids` - The devices to delete.
+/// * `password` - The account password, needed if the server asks for UIAA.
+/// * `state` - The client state containing the Matrix client to delete the devices with.
+/// * `app_handle` - The app handle used to open the account management page.
+#[tauri::command]
+pub async fn delete_devices(
+    device_ids: Vec<String>,
+    password: Option<String>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    if is_oauth(client) {
+        open_account_management(client, &app_handle)
+            .await
+            .map_err(|e| format!("Failed to open account management: {}", e))?;
+        return Ok("account management opened".into());
+    }
+
+    let device_ids: Vec<OwnedDeviceId> = device_ids.into_iter().map(OwnedDeviceId::from).collect();
+    if client.device_id().is_some_and(|current| device_ids.iter().any(|d| d == current)) {
+        return Err("Use logout to sign out of the current device".to_string());
+    }
+
+    if let Err(e) = client.delete_devices(&device_ids, None).await {
+        let Some(uiaa_info) = e.as_uiaa_response() else {
+            return Err(format!("Failed to delete devices: {}", e));
+        };
+        debug!("UIAA required to delete devices");
+        let password = password.ok_or("Password required for UIAA authentication")?;
+        let user_id = client.user_id().ok_or("Not logged in")?;
+
+        client
+            .delete_devices(&device_ids, Some(password_auth(user_id, password, uiaa_info.session.clone())))
+            .await
+            .map_err(|e| format!("Failed to delete devices: {}", e))?;
+    }
+
+    Ok("devices deleted".into())
+}
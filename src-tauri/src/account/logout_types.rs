@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum LogoutMode {
+    /// Invalidate the session on the server and delete everything stored locally for the account.
+    SignOut,
+    /// Drop the client from memory but keep the session and store, so it can be restored later.
+    #[default]
+    Lock,
+}
@@ -0,0 +1,26 @@
+use matrix_sdk::{AuthApi, Client};
+use tauri::AppHandle;
+use tauri_plugin_opener::OpenerExt;
+
+/// Whether the client is logged in through OAuth (next-gen auth), in which case account changes
+/// like deleting devices or changing the password happen on the server's account management page
+/// instead of through UIAA.
+pub fn is_oauth(client: &Client) -> bool {
+    matches!(client.auth_api(), Some(AuthApi::OAuth(_)))
+}
+
+/// Open the server's account management page in the browser.
+///
+/// # Arguments
+/// * `client` - The OAuth-authenticated Matrix client.
+/// * `app_handle` - The app handle used to open the browser.
+pub async fn open_account_management(client: &Client, app_handle: &AppHandle) -> anyhow::Result<()> {
+    let url = client
+        .oauth()
+        .account_management_url()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("The server does not advertise an account management page"))?
+        .build();
+    app_handle.opener().open_url(url.as_str(), None::<&str>)?;
+    Ok(())
+}
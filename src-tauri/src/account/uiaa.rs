@@ -0,0 +1,17 @@
+use ruma::api::client::uiaa::{AuthData, Password, UserIdentifier};
+use ruma::UserId;
+
+/// Build the password stage of a user-interactive auth flow for `user_id`.
+///
+/// # Arguments
+/// * `user_id` - The user the password belongs to.
+/// * `password` - The account password.
+/// * `session` - The UIAA session returned by the server's first (unauthenticated) response.
+pub fn password_auth(user_id: &UserId, password: String, session: Option<String>) -> AuthData {
+    let mut password_auth = Password::new(
+        UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
+        password,
+    );
+    password_auth.session = session;
+    AuthData::Password(password_auth)
+}
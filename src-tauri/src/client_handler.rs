@@ -1,31 +1,56 @@
 use crate::account::account_reset_types::AccountResetType;
+use crate::account::devices::default_device_name;
+use crate::account::uiaa::password_auth;
+use crate::encryption::recovery::{report_security_status, watch_recovery_state};
+use crate::encryption::trust::watch_identity_changes;
+use crate::encryption::utd::UtdTracker;
 use crate::events::client_events::ClientEvents;
+use crate::messages::outbox::Outbox;
+use crate::settings::account_settings::AccountSettings;
 use crate::sync_manager::SyncManager;
 use crate::SecretState;
 use crate::StoreState;
 use matrix_sdk::authentication::matrix::MatrixSession;
+use matrix_sdk::crypto::CollectStrategy;
 use matrix_sdk::authentication::oauth::registration::{
     ApplicationType, ClientMetadata, Localized, OAuthGrantType,
 };
 use matrix_sdk::authentication::oauth::UrlOrQuery;
+use matrix_sdk::encryption::recovery::IdentityResetHandle;
 use matrix_sdk::encryption::CrossSigningResetAuthType;
 use matrix_sdk::utils::local_server::LocalServerBuilder;
 use matrix_sdk::{
     ruma::api::client::account::register::v3::Request as RegistrationRequest, AuthSession, Client,
     SessionMeta, SessionTokens,
 };
-use ruma::api::client::uiaa::{AuthData, Password, RegistrationToken, UserIdentifier};
+use ruma::api::client::uiaa::{AuthData, RegistrationToken};
 use ruma::serde::Raw;
 use ruma::{OwnedDeviceId, OwnedUserId};
-use std::path::Path;
-use tauri::{AppHandle, Manager, Url};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, Manager, Url};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tauri_plugin_opener::OpenerExt;
 use tracing::{debug, error};
 use crate::secret::{SecretService, Session};
 
+/// Holds the identity reset waiting for OAuth approval. Shared so a reset can be awaited without
+/// keeping the [`crate::ClientState`] lock.
+pub type IdentityResetSlot = Arc<Mutex<Option<Arc<IdentityResetHandle>>>>;
+
 pub struct ClientHandler {
     matrix_client: Client,
     pub sync_manager: SyncManager,
+    pub outbox: Arc<Outbox>,
+    /// The identity reset waiting for OAuth approval, if any, so it can be cancelled.
+    identity_reset: IdentityResetSlot,
+    /// Background tasks watching the client, aborted when the session ends so they don't keep
+    /// the client (and its sqlite store) alive.
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    /// Which devices get our room keys. It can only be set when building the client, so this is
+    /// what the account settings said at the time.
+    room_key_strategy: CollectStrategy,
     app_handle: AppHandle,
 }
 
@@ -35,7 +60,11 @@ impl ClientHandler {
             matrix_client: Client::new("https://matrix.org".parse().unwrap())
                 .await
                 .expect("Failed to create Matrix client"),
-            sync_manager: SyncManager::new(),
+            sync_manager: SyncManager::new(app_handle.clone()),
+            outbox: Arc::new(Outbox::new(app_handle.clone())),
+            identity_reset: Arc::new(Mutex::new(None)),
+            tasks: Mutex::new(Vec::new()),
+            room_key_strategy: AccountSettings::default().room_key_strategy(),
             app_handle,
         }
     }
@@ -44,6 +73,35 @@ impl ClientHandler {
         &self.matrix_client
     }
 
+    pub fn room_key_strategy(&self) -> &CollectStrategy {
+        &self.room_key_strategy
+    }
+
+    pub fn identity_reset_slot(&self) -> IdentityResetSlot {
+        self.identity_reset.clone()
+    }
+
+    /// Check the cross-signing, backup and recovery state of the account in the background and
+    /// report it to the frontend as a `security:status` event, then keep reporting recovery state
+    /// changes as `recovery:state` events. Also starts watching for new room keys and identity
+    /// changes. The tasks run until [`ClientHandler::stop_background_tasks`] is called.
+    pub async fn spawn_background_tasks(&self) {
+        let client = &self.matrix_client;
+        let app_handle = &self.app_handle;
+        let mut tasks = self.tasks.lock().await;
+        tasks.push(tokio::spawn(report_security_status(client.clone(), app_handle.clone())));
+        tasks.push(tokio::spawn(watch_recovery_state(client.clone(), app_handle.clone())));
+        tasks.push(tokio::spawn(UtdTracker::watch_room_keys(client.clone(), app_handle.clone())));
+        tasks.push(tokio::spawn(watch_identity_changes(client.clone(), app_handle.clone())));
+    }
+
+    /// Abort the tasks started by [`ClientHandler::spawn_background_tasks`].
+    pub async fn stop_background_tasks(&self) {
+        for task in self.tasks.lock().await.drain(..) {
+            task.abort();
+        }
+    }
+
     pub async fn register(
         &self,
         username: String,
@@ -51,11 +109,17 @@ impl ClientHandler {
         homeserver: String,
         registration_token: Option<String>,
     ) -> anyhow::Result<ClientHandler> {
-        let client: Client = self.get_new_client(&username, &homeserver, None).await?;
+        // a new account has no settings yet
+        let local_user_id = Self::local_user_id(&username, &homeserver)?;
+        let strategy = AccountSettings::default().room_key_strategy();
+        let client: Client = self
+            .get_new_client(&local_user_id, &homeserver, None, strategy.clone())
+            .await?;
 
         let mut registration_request = RegistrationRequest::new();
         registration_request.username = Some(username.clone());
         registration_request.password = Some(password.clone());
+        registration_request.initial_device_display_name = Some(default_device_name());
         if let Some(token) = registration_token.clone() {
             registration_request.auth =
                 Some(AuthData::RegistrationToken(RegistrationToken::new(token)));
@@ -72,7 +136,11 @@ impl ClientHandler {
                 );
                 Ok(ClientHandler {
                     matrix_client: client,
-                    sync_manager: SyncManager::new(),
+                    sync_manager: SyncManager::new(self.app_handle.clone()),
+                    outbox: Arc::new(Outbox::new(self.app_handle.clone())),
+                    identity_reset: Arc::new(Mutex::new(None)),
+                    tasks: Mutex::new(Vec::new()),
+                    room_key_strategy: strategy,
                     app_handle: self.app_handle.clone(),
                 })
             }
@@ -95,7 +163,11 @@ impl ClientHandler {
                             );
                             Ok(ClientHandler {
                                 matrix_client: client,
-                                sync_manager: SyncManager::new(),
+                                sync_manager: SyncManager::new(self.app_handle.clone()),
+                                outbox: Arc::new(Outbox::new(self.app_handle.clone())),
+                                identity_reset: Arc::new(Mutex::new(None)),
+                                tasks: Mutex::new(Vec::new()),
+                                room_key_strategy: strategy,
                                 app_handle: self.app_handle.clone(),
                             })
                         }
@@ -115,25 +187,77 @@ impl ClientHandler {
         }
     }
 
+    /// Directory of the sqlite store for `user_id`.
+    ///
+    /// # Arguments
+    /// * `app_handle` - The app handle used to find the app data directory.
+    /// * `user_id` - The user ID whose store directory should be returned.
+    fn store_dir(app_handle: &AppHandle, user_id: &str) -> anyhow::Result<PathBuf> {
+        Ok(Path::join(
+            &app_handle.path().app_data_dir()?.join("accounts"),
+            SecretService::user_id_hash(user_id),
+        ))
+    }
+
+    /// Delete everything stored on this device for `user_id`: the account entry in
+    /// [`crate::store::EchelonStore`], its secrets and keyring entry, its settings and the sqlite
+    /// store. The client for the account must have been dropped already, otherwise the store is
+    /// still open.
+    ///
+    /// # Arguments
+    /// * `app_handle` - The app handle used to access the stores.
+    /// * `user_id` - The user ID whose data should be removed.
+    pub fn remove_local_data(app_handle: &AppHandle, user_id: &str) -> anyhow::Result<()> {
+        app_handle.state::<StoreState>().0.remove_account(user_id)?;
+        app_handle.state::<SecretState>().0.remove_user(user_id)?;
+        AccountSettings::remove(app_handle, user_id)?;
+
+        match std::fs::remove_dir_all(Self::store_dir(app_handle, user_id)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The user ID the local data of `username` on `homeserver` is stored under. It is derived
+    /// from the homeserver URL before logging in, so it isn't necessarily the real user ID, e.g.
+    /// when the server name is delegated to another host.
+    ///
+    /// # Arguments
+    /// * `username` - The localpart the user logs in with.
+    /// * `homeserver` - The URL of the homeserver.
+    fn local_user_id(username: &str, homeserver: &str) -> anyhow::Result<String> {
+        let url = Url::parse(homeserver)?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("Homeserver URL has no host"))?;
+        Ok(format!("@{}:{}", username, host))
+    }
+
+    /// Build a client for `new_homeserver`, storing its data in the sqlite store of
+    /// `local_user_id`.
+    ///
+    /// # Arguments
+    /// * `local_user_id` - The user ID the store belongs to, see [`ClientHandler::local_user_id`].
+    /// * `new_homeserver` - The URL of the homeserver.
+    /// * `sqlite_pwd` - The passphrase of the sqlite store.
+    /// * `room_key_strategy` - Which devices get our room keys, from the account settings.
     async fn get_new_client(
         &self,
-        username: &String,
+        local_user_id: &str,
         new_homeserver: &String,
         sqlite_pwd: Option<String>,
+        room_key_strategy: CollectStrategy,
     ) -> anyhow::Result<Client> {
         Ok(Client::builder()
             .homeserver_url(new_homeserver)
             .sqlite_store(
-                Path::join(
-                    &self.app_handle.path().app_data_dir()?.join("accounts"),
-                    SecretService::user_id_hash(&format!(
-                        "@{}:{}",
-                        username,
-                        Url::parse(new_homeserver)?.domain().unwrap()
-                    )),
-                ),
+                Self::store_dir(&self.app_handle, local_user_id)?,
                 sqlite_pwd.as_deref(),
             )
+            .with_room_key_recipient_strategy(room_key_strategy)
+            // share the keys to the room history with users we invite (MSC3061)
+            .with_enable_share_history_on_invite(true)
             .build()
             .await?)
     }
@@ -144,7 +268,14 @@ impl ClientHandler {
     /// * `homeserver_url` - The URL of the homeserver to create a client for OAuth.
     async fn get_oauth_client(&self, new_homeserver: &String) -> anyhow::Result<Client> {
         let homeserver_url: Url = Url::parse(new_homeserver)?;
-        let client = Client::new(homeserver_url).await?;
+        // the account, and so its settings and store, isn't known until the login is done. This
+        // client only does the login, the session is moved to the account's store afterwards
+        let client = Client::builder()
+            .homeserver_url(homeserver_url)
+            .with_room_key_recipient_strategy(AccountSettings::default().room_key_strategy())
+            .with_enable_share_history_on_invite(true)
+            .build()
+            .await?;
         Ok(client)
     }
 
@@ -153,25 +284,45 @@ impl ClientHandler {
         username: String,
         password: String,
         homeserver: String,
+        device_name: Option<String>,
     ) -> anyhow::Result<Option<ClientHandler>> {
         // Derive the full Matrix user ID so we can look up / generate the sqlite password
         // before we even open the store, ensuring the DB is always encrypted from first open.
-        let user_id = format!(
-            "@{}:{}",
-            username,
-            Url::parse(&homeserver)?.domain().unwrap_or(&homeserver)
-        );
+        let user_id = Self::local_user_id(&username, &homeserver)?;
         let secrets = self.app_handle.state::<SecretState>();
         let sqlite_pwd = secrets.0.get_or_create_sqlite_pwd(&user_id)?;
 
-        let new_client = self.get_new_client(&username, &homeserver, Some(sqlite_pwd)).await?;
+        let strategy = AccountSettings::load(&self.app_handle, &user_id)?.room_key_strategy();
+        let new_client = self
+            .get_new_client(&user_id, &homeserver, Some(sqlite_pwd.clone()), strategy.clone())
+            .await?;
         new_client
             .matrix_auth()
             .login_username(&username, &password)
-            .initial_device_display_name("Echelon")
+            .initial_device_display_name(&device_name.unwrap_or_else(default_device_name))
             .send()
             .await?;
 
+        // settings are saved under the real user ID, which differs from the one derived from the
+        // homeserver URL when the server name is delegated. The key sharing strategy can only be
+        // set when building the client, so reopen the store with the right one
+        let real_user_id = new_client.user_id().unwrap().to_string();
+        let (new_client, strategy) = if real_user_id != user_id {
+            let session = new_client
+                .session()
+                .ok_or_else(|| anyhow::anyhow!("Login didn't return a session"))?;
+            let strategy = AccountSettings::load(&self.app_handle, &real_user_id)?.room_key_strategy();
+            drop(new_client);
+
+            let client = self
+                .get_new_client(&user_id, &homeserver, Some(sqlite_pwd), strategy.clone())
+                .await?;
+            client.restore_session(session).await?;
+            (client, strategy)
+        } else {
+            (new_client, strategy)
+        };
+
         ClientEvents::register_events(&new_client, self.app_handle.clone());
 
         // store the session tokens in stronghold
@@ -189,7 +340,11 @@ impl ClientHandler {
 
         Ok(Some(ClientHandler {
             matrix_client: new_client,
-            sync_manager: SyncManager::new(),
+            sync_manager: SyncManager::new(self.app_handle.clone()),
+            outbox: Arc::new(Outbox::new(self.app_handle.clone())),
+            identity_reset: Arc::new(Mutex::new(None)),
+            tasks: Mutex::new(Vec::new()),
+            room_key_strategy: strategy,
             app_handle: self.app_handle.clone(),
         }))
     }
@@ -254,9 +409,27 @@ impl ClientHandler {
             .finish_login(UrlOrQuery::Query(query.to_string()))
             .await?;
 
+        // now that the account is known, move the session to its sqlite store, built with the key
+        // sharing strategy from its settings. Nothing was synced yet, so no keys were shared with
+        // the login client
+        let real_user_id = new_client.user_id().unwrap().to_owned();
+        let session = new_client
+            .session()
+            .ok_or_else(|| anyhow::anyhow!("Login didn't return a session"))?;
+        let local_user_id = Self::local_user_id(real_user_id.localpart(), &homeserver)?;
+        let secrets = self.app_handle.state::<SecretState>();
+        let sqlite_pwd = secrets.0.get_or_create_sqlite_pwd(&local_user_id)?;
+        let strategy = AccountSettings::load(&self.app_handle, real_user_id.as_str())?.room_key_strategy();
+        drop(oauth);
+        drop(new_client);
+
+        let new_client = self
+            .get_new_client(&local_user_id, &homeserver, Some(sqlite_pwd), strategy.clone())
+            .await?;
+        new_client.restore_session(session).await?;
+
         // store the session tokens in stronghold
         let session_tokens = new_client.session_tokens().unwrap();
-        let secrets = self.app_handle.state::<SecretState>();
         secrets.0.set_session(&Session {
             user_id: new_client.user_id().unwrap().to_string(),
             device_id: new_client.device_id().map(|d| d.to_string()).unwrap_or_default(),
@@ -272,7 +445,11 @@ impl ClientHandler {
 
         Ok(Some(ClientHandler {
             matrix_client: new_client,
-            sync_manager: SyncManager::new(),
+            sync_manager: SyncManager::new(self.app_handle.clone()),
+            outbox: Arc::new(Outbox::new(self.app_handle.clone())),
+            identity_reset: Arc::new(Mutex::new(None)),
+            tasks: Mutex::new(Vec::new()),
+            room_key_strategy: strategy,
             app_handle: self.app_handle.clone(),
         }))
     }
@@ -282,39 +459,40 @@ impl ClientHandler {
         username: String,
         homeserver: String,
     ) -> anyhow::Result<Option<ClientHandler>> {
-        let user_id = format!(
-            "@{}:{}",
-            username,
-            Url::parse(&homeserver)?.domain().unwrap_or(&homeserver)
-        );
+        let user_id = Self::local_user_id(&username, &homeserver)?;
         let secrets = self.app_handle.state::<SecretState>();
         let sqlite_pwd = secrets.0.get_sqlite_pwd(&user_id)?;
 
-        let new_client = self.get_new_client(&username, &homeserver, sqlite_pwd).await?;
-        match secrets.0.get_session(&*user_id) {
-            Ok(session_opt) => {
-                match session_opt {
-                    None => {
-                        error!("No session found for user, cannot restore");
-                    }
-                    Some(session) => {
-                        new_client
-                            .restore_session(AuthSession::Matrix(MatrixSession {
-                                meta: SessionMeta {
-                                    user_id: OwnedUserId::try_from(session.user_id)?,
-                                    device_id: OwnedDeviceId::try_from(session.device_id)?,
-                                },
-                                tokens: SessionTokens {
-                                    access_token: session.access_token,
-                                    refresh_token: session.refresh_token,
-                                }
-                            }))
-                            .await?
-                    }
-                }
-            }
+        let session_opt = match secrets.0.get_session(&*user_id) {
+            Ok(session_opt) => session_opt,
             Err(e) => {
                 error!("Some other error on trying to make session: {e}");
+                None
+            }
+        };
+
+        // settings are saved under the real user ID, which the session knows
+        let settings_user_id = session_opt.as_ref().map_or(user_id.as_str(), |s| s.user_id.as_str());
+        let strategy = AccountSettings::load(&self.app_handle, settings_user_id)?.room_key_strategy();
+
+        let new_client = self.get_new_client(&user_id, &homeserver, sqlite_pwd, strategy.clone()).await?;
+        match session_opt {
+            None => {
+                error!("No session found for user, cannot restore");
+            }
+            Some(session) => {
+                new_client
+                    .restore_session(AuthSession::Matrix(MatrixSession {
+                        meta: SessionMeta {
+                            user_id: OwnedUserId::try_from(session.user_id)?,
+                            device_id: OwnedDeviceId::try_from(session.device_id)?,
+                        },
+                        tokens: SessionTokens {
+                            access_token: session.access_token,
+                            refresh_token: session.refresh_token,
+                        }
+                    }))
+                    .await?
             }
         }
 
@@ -323,46 +501,50 @@ impl ClientHandler {
 
         Ok(Some(ClientHandler {
             matrix_client: new_client,
-            sync_manager: SyncManager::new(),
+            sync_manager: SyncManager::new(self.app_handle.clone()),
+            outbox: Arc::new(Outbox::new(self.app_handle.clone())),
+            identity_reset: Arc::new(Mutex::new(None)),
+            tasks: Mutex::new(Vec::new()),
+            room_key_strategy: strategy,
             app_handle: self.app_handle.clone(),
         }))
     }
 
+    /// Reset the cross-signing identity or the key backup of the account. Takes the client and
+    /// the identity reset slot instead of `&self`, an OAuth identity reset can wait for approval
+    /// for a long time and the caller shouldn't hold the [`crate::ClientState`] lock meanwhile.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client of the account to reset.
+    /// * `app_handle` - The app handle used to open the approval page.
+    /// * `identity_reset` - Where the pending OAuth identity reset is kept, so it can be cancelled.
+    /// * `account_reset_type` - Which reset to perform.
+    /// * `password` - The account password, required for an identity reset on a UIAA server.
     pub async fn reset_account(
-        &self,
+        client: Client,
+        app_handle: AppHandle,
+        identity_reset: IdentityResetSlot,
         account_reset_type: AccountResetType,
         password: Option<String>,
-        key_backup: Option<String>,
-    ) -> anyhow::Result<()> {
-        let client = &self.matrix_client;
+    ) -> anyhow::Result<Option<String>> {
         let recovery = client.encryption().recovery();
 
         match account_reset_type {
             AccountResetType::IdentityReset => {
                 debug!("Starting identity reset...");
                 if let Some(handle) = recovery.reset_identity().await? {
+                    let handle = Arc::new(handle);
                     match handle.auth_type() {
                         CrossSigningResetAuthType::Uiaa(uiaa_info) => {
                             debug!("UIAA authentication required for identity reset");
                             if let Some(pwd) = password {
-                                // Create password authentication data
                                 let user_id = client
                                     .user_id()
                                     .ok_or_else(|| anyhow::anyhow!("No user ID available"))?;
 
-                                let mut password_auth = Password::new(
-                                    UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
-                                    pwd,
-                                );
-
-                                // Set the session if available
-                                if let Some(session) = &uiaa_info.session {
-                                    password_auth.session = Some(session.clone());
-                                }
-
                                 // Perform the reset with password authentication
                                 handle
-                                    .reset(Some(AuthData::Password(password_auth)))
+                                    .reset(Some(password_auth(user_id, pwd, uiaa_info.session.clone())))
                                     .await?;
                                 debug!("Identity reset completed successfully");
                             } else {
@@ -372,28 +554,68 @@ impl ClientHandler {
                             }
                         }
                         CrossSigningResetAuthType::OAuth(oauth_info) => {
-                            debug!("OAuth authentication required: {:?}", oauth_info);
-                            // For OAuth, the user needs to complete authentication via browser
-                            // This typically requires opening a browser and completing the OAuth flow
-                            handle.reset(None).await?;
-                            debug!("Identity reset initiated with OAuth");
+                            debug!("OAuth approval required for identity reset: {}", oauth_info.approval_url);
+                            // the reset has to be approved on the server's account management page,
+                            // the sdk keeps retrying the upload until that happens or we cancel
+                            *identity_reset.lock().await = Some(handle.clone());
+
+                            app_handle
+                                .opener()
+                                .open_url(oauth_info.approval_url.as_str(), None::<&str>)?;
+                            app_handle.emit(
+                                "identity_reset:waiting_for_approval",
+                                oauth_info.approval_url.to_string(),
+                            )?;
+
+                            let result = handle.reset(None).await;
+                            // cancelling takes the handle out of the slot and makes `reset`
+                            // return without an error, so only a handle that is still there
+                            // means the reset was approved
+                            let cancelled = {
+                                let mut slot = identity_reset.lock().await;
+                                if slot.as_ref().is_some_and(|h| Arc::ptr_eq(h, &handle)) {
+                                    slot.take();
+                                    false
+                                } else {
+                                    true
+                                }
+                            };
+                            result?;
+                            if cancelled {
+                                return Err(anyhow::anyhow!("Identity reset was cancelled"));
+                            }
+                            debug!("Identity reset completed after OAuth approval");
                         }
                     }
                 }
+
+                // resetting the identity throws away secret storage and the backup, set up a
+                // fresh recovery so the user isn't left without one
+                let recovery_key = recovery.enable().wait_for_backups_to_upload().await?;
+                return Ok(Some(recovery_key));
             }
             AccountResetType::KeyBackupReset => {
                 debug!("Starting key backup reset...");
-
-                if let Some(key_backup) = key_backup {
-                    recovery.recover(&key_backup).await?;
-                } else {
-                    Err(anyhow::anyhow!(
-                        "KeyBackup reset required for key backup reset"
-                    ))?;
-                }
+                // throws away the current recovery key, recovering with an existing one is done
+                // through `recover_with_key` instead
+                let recovery_key = recovery.reset_key().await?;
+                debug!("Key backup reset completed successfully");
+                return Ok(Some(recovery_key));
             }
         }
 
+        Ok(None)
+    }
+
+    /// Cancel an identity reset that is waiting for OAuth approval.
+    pub async fn cancel_identity_reset(&self) -> anyhow::Result<()> {
+        let handle = self
+            .identity_reset
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("No identity reset waiting for approval"))?;
+        handle.cancel().await;
         Ok(())
     }
 }
\ No newline at end of file
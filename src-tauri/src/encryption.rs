@@ -0,0 +1,6 @@
+pub(crate) mod encryption_types;
+pub(crate) mod recovery;
+pub(crate) mod room_keys;
+pub(crate) mod trust;
+pub(crate) mod utd;
+pub(crate) mod verification;
\ No newline at end of file
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+/// An incoming verification request, emitted as `verification:request` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationRequestPayload {
+    pub flow_id: String,
+    pub user_id: String,
+    /// The device that sent the request, if known (only for to-device requests).
+    pub device_id: Option<String>,
+    /// Whether this is one of our own devices asking to be verified.
+    pub is_self_verification: bool,
+}
+
+/// The stages a verification flow goes through, emitted as `verification:state` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum VerificationStage {
+    Requested,
+    Ready,
+    /// Both sides agreed on a method and the actual verification started.
+    Started { method: VerificationMethod },
+    /// The other side scanned our QR code and we need to confirm they did.
+    QrScanned,
+    Done,
+    Cancelled { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMethod {
+    Sas,
+    Qr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationStatePayload {
+    pub flow_id: String,
+    pub user_id: String,
+    #[serde(flatten)]
+    pub stage: VerificationStage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SasEmoji {
+    pub symbol: String,
+    pub description: String,
+}
+
+/// The short authentication string both users have to compare, emitted as `verification:sas`
+/// events once the keys have been exchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SasPayload {
+    pub flow_id: String,
+    pub user_id: String,
+    /// The seven emojis to compare, if both sides support emoji SAS.
+    pub emojis: Option<Vec<SasEmoji>>,
+    pub decimals: (u16, u16, u16),
+}
+
+/// Mirror of the sdk's `RecoveryState`, which isn't serializable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryStatus {
+    /// We haven't been able to check yet, usually because the first sync isn't done.
+    Unknown,
+    /// Secret storage is set up and we have all the secrets locally.
+    Enabled,
+    /// Secret storage isn't set up on the account.
+    Disabled,
+    /// Secret storage is set up, but this device is missing some of the secrets.
+    Incomplete,
+}
+
+/// The state of the account's encryption setup, emitted as `security:status` events after login
+/// so the frontend can nudge the user into setting up recovery or verifying the device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityStatus {
+    pub has_master_key: bool,
+    pub has_self_signing_key: bool,
+    pub has_user_signing_key: bool,
+    /// Whether this device has all three cross-signing private keys.
+    pub cross_signing_complete: bool,
+    /// Whether key backup is enabled on this device.
+    pub backup_enabled: bool,
+    /// Whether there is a key backup on the server at all.
+    pub backup_exists_on_server: bool,
+    pub recovery: RecoveryStatus,
+}
+
+/// Returned once by `setup_recovery`, the frontend has to make sure the user saves the key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoverySetup {
+    pub recovery_key: String,
+}
+
+/// Steps of `recover_with_key`, emitted as `recovery:progress` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum RecoveryProgress {
+    /// Fetching the cross-signing keys and backup key from secret storage.
+    ImportingSecrets,
+    /// Downloading the room keys of encrypted rooms from the key backup.
+    DownloadingKeys { current: usize, total: usize },
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryErrorKind {
+    /// The recovery key or passphrase doesn't match the one secret storage was set up with.
+    WrongKey,
+    /// There is no secret storage or key backup on the server to recover from.
+    NoBackup,
+    Other,
+}
+
+/// Error returned by `recover_with_key`, typed so the frontend can tell a typo apart from an
+/// account that simply has nothing to recover.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryError {
+    pub kind: RecoveryErrorKind,
+    pub message: String,
+}
+
+/// Steps of `import_room_keys`, emitted as `room_keys:import_progress` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum KeyImportProgress {
+    /// Decrypting the key file and importing the keys into the crypto store.
+    Importing,
+    /// Retrying events in the affected rooms that failed to decrypt before.
+    RetryingDecryption { rooms: usize },
+    Done,
+}
+
+/// Outcome of a room key import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyImportResult {
+    pub imported: usize,
+    /// Keys that were in the file but that we already had (in the same or a better version).
+    pub skipped: usize,
+    /// The rooms the newly imported keys belong to.
+    pub rooms: Vec<String>,
+}
+
+/// Why an event couldn't be decrypted, coarsened from the sdk's reasons into what the UI can
+/// explain to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UtdReason {
+    /// We don't have the room key (yet), it may still arrive from backup or another device.
+    MissingKey,
+    /// The sender deliberately didn't share the key with us.
+    Withheld,
+    /// The key is there, but the sending device isn't trusted enough for our settings.
+    UnverifiedSenderDevice,
+    /// The message is from before this device logged in and there is no usable key backup.
+    Historical,
+    Other,
+}
+
+/// Placeholder for an event we couldn't decrypt, emitted as `matrix:utd` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UtdPayload {
+    pub room_id: String,
+    pub event_id: String,
+    pub sender: String,
+    pub reason: UtdReason,
+}
+
+/// Number of undecryptable events seen in a room, returned by `get_utd_diagnostics`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UtdRoomStats {
+    /// Events that still can't be decrypted.
+    pub pending: usize,
+    /// Events that got decrypted later, once their key arrived.
+    pub resolved: usize,
+}
+
+/// Emitted as `identity:changed` when a user whose identity we pinned or verified shows up with
+/// a different one, so the user can re-verify them or accept the change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityChangedPayload {
+    pub user_id: String,
+    /// Whether we had verified the previous identity. Depending on the settings, sending to rooms
+    /// with this user is blocked until the change is dealt with.
+    pub was_verified: bool,
+}
@@ -0,0 +1,252 @@
+use futures_util::StreamExt;
+use matrix_sdk::encryption::recovery::{RecoveryError as SdkRecoveryError, RecoveryState};
+use matrix_sdk::encryption::secret_storage::SecretStorageError;
+use matrix_sdk::Client;
+use tauri::{AppHandle, Emitter, State};
+use tracing::{debug, error};
+use crate::account::uiaa::password_auth;
+use crate::encryption::encryption_types::{
+    RecoveryError, RecoveryErrorKind, RecoveryProgress, RecoverySetup, RecoveryStatus,
+    SecurityStatus,
+};
+use crate::ClientState;
+
+impl From<RecoveryState> for RecoveryStatus {
+    fn from(state: RecoveryState) -> Self {
+        match state {
+            RecoveryState::Enabled => RecoveryStatus::Enabled,
+            RecoveryState::Disabled => RecoveryStatus::Disabled,
+            RecoveryState::Incomplete => RecoveryStatus::Incomplete,
+            _ => RecoveryStatus::Unknown,
+        }
+    }
+}
+
+/// Collect the cross-signing, backup and recovery state of the account.
+///
+/// # Arguments
+/// * `client` - The Matrix client to inspect.
+pub async fn security_status(client: &Client) -> SecurityStatus {
+    let encryption = client.encryption();
+    let cross_signing = encryption.cross_signing_status().await;
+    let backups = encryption.backups();
+
+    SecurityStatus {
+        has_master_key: cross_signing.as_ref().is_some_and(|s| s.has_master),
+        has_self_signing_key: cross_signing.as_ref().is_some_and(|s| s.has_self_signing),
+        has_user_signing_key: cross_signing.as_ref().is_some_and(|s| s.has_user_signing),
+        cross_signing_complete: cross_signing.as_ref().is_some_and(|s| s.is_complete()),
+        backup_enabled: backups.are_enabled().await,
+        backup_exists_on_server: backups.exists_on_server().await.unwrap_or_else(|e| {
+            error!("Failed to check for a key backup on the server: {}", e);
+            false
+        }),
+        recovery: encryption.recovery().state().into(),
+    }
+}
+
+/// Run the post-login security check and emit the result as a `security:status` event. Waits for
+/// the sdk to finish setting up encryption first, otherwise everything reads as missing.
+///
+/// # Arguments
+/// * `client` - The Matrix client that just logged in.
+/// * `app_handle` - The app handle used to emit the event.
+pub async fn report_security_status(client: Client, app_handle: AppHandle) {
+    client.encryption().wait_for_e2ee_initialization_tasks().await;
+
+    let status = security_status(&client).await;
+    debug!("Post-login security status: {:?}", status);
+    if let Err(e) = app_handle.emit("security:status", status) {
+        error!("Failed to emit security status: {}", e);
+    }
+}
+
+/// Emit a `recovery:state` event every time the recovery state of the account changes, e.g. when
+/// another device sets up recovery or this device imports the secrets.
+///
+/// # Arguments
+/// * `client` - The Matrix client to watch.
+/// * `app_handle` - The app handle used to emit the events.
+pub async fn watch_recovery_state(client: Client, app_handle: AppHandle) {
+    let mut states = client.encryption().recovery().state_stream();
+    while let Some(state) = states.next().await {
+        if let Err(e) = app_handle.emit("recovery:state", RecoveryStatus::from(state)) {
+            error!("Failed to emit recovery state: {}", e);
+        }
+    }
+}
+
+fn emit_progress(app_handle: &AppHandle, progress: RecoveryProgress) {
+    if let Err(e) = app_handle.emit("recovery:progress", progress) {
+        error!("Failed to emit recovery progress: {}", e);
+    }
+}
+
+/// Get the cross-signing, backup and recovery state of the current account.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client to inspect.
+#[tauri::command]
+pub async fn get_security_status(
+    state: State<'_, ClientState>,
+) -> Result<SecurityStatus, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    Ok(security_status(client_handler.get_client()).await)
+}
+
+/// Set up cross-signing (if the account doesn't have it yet), key backup and secret storage.
+/// The generated recovery key is only ever returned here, so the frontend has to make the user
+/// save it.
+///
+/// # Arguments
+/// * `passphrase` - An optional passphrase the recovery key is derived from, so the user can
+///   recover with either.
+/// * `password` - The account password, needed if the server asks for UIAA to upload the
+///   cross-signing keys.
+/// * `state` - The client state containing the Matrix client to set up recovery for.
+#[tauri::command]
+pub async fn setup_recovery(
+    passphrase: Option<String>,
+    password: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<RecoverySetup, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let encryption = client.encryption();
+
+    // uploading the cross-signing keys usually needs UIAA, try without auth first to get a session
+    if let Err(e) = encryption.bootstrap_cross_signing_if_needed(None).await {
+        let Some(uiaa_info) = e.as_uiaa_response() else {
+            return Err(format!("Failed to bootstrap cross-signing: {}", e));
+        };
+        let password = password.ok_or("Password required for UIAA authentication")?;
+        let user_id = client.user_id().ok_or("Not logged in")?;
+
+        encryption
+            .bootstrap_cross_signing_if_needed(Some(password_auth(user_id, password, uiaa_info.session.clone())))
+            .await
+            .map_err(|e| format!("Failed to bootstrap cross-signing: {}", e))?;
+    }
+    debug!("Cross-signing is set up, enabling recovery");
+
+    let recovery = encryption.recovery();
+    let enable = recovery.enable().wait_for_backups_to_upload();
+    let recovery_key = match passphrase.as_deref() {
+        Some(passphrase) => enable.with_passphrase(passphrase).await,
+        None => enable.await,
+    }
+    .map_err(|e| format!("Failed to enable recovery: {}", e))?;
+
+    Ok(RecoverySetup { recovery_key })
+}
+
+/// Get the recovery state of the current account. Changes are also pushed as `recovery:state`
+/// events.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client to inspect.
+#[tauri::command]
+pub async fn get_recovery_state(
+    state: State<'_, ClientState>,
+) -> Result<RecoveryStatus, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    Ok(client_handler.get_client().encryption().recovery().state().into())
+}
+
+/// Verify this device by importing the secrets from secret storage with the recovery key (or the
+/// passphrase it was derived from), then download the room keys of every encrypted room from the
+/// key backup, if there is one. Progress is reported through `recovery:progress` events.
+///
+/// # Arguments
+/// * `recovery_key` - The recovery key or passphrase.
+/// * `state` - The client state containing the Matrix client to recover.
+/// * `app_handle` - The app handle used to emit the progress events.
+#[tauri::command]
+pub async fn recover_with_key(
+    recovery_key: String,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<RecoveryStatus, RecoveryError> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let encryption = client.encryption();
+    let recovery = encryption.recovery();
+    let backups = encryption.backups();
+
+    let other = |message: String| RecoveryError { kind: RecoveryErrorKind::Other, message };
+
+    // check for something to recover from first, so missing secret storage isn't reported as a
+    // bad key
+    let secret_storage_enabled = encryption
+        .secret_storage()
+        .is_enabled()
+        .await
+        .map_err(|e| other(e.to_string()))?;
+    if !secret_storage_enabled {
+        return Err(RecoveryError {
+            kind: RecoveryErrorKind::NoBackup,
+            message: "Recovery isn't set up on the server".to_string(),
+        });
+    }
+
+    emit_progress(&app_handle, RecoveryProgress::ImportingSecrets);
+    if let Err(e) = recovery.recover(&recovery_key).await {
+        let kind = match e {
+            // the key doesn't decode, or doesn't match the secret storage key
+            SdkRecoveryError::SecretStorage(SecretStorageError::SecretStorageKey(_)) => {
+                RecoveryErrorKind::WrongKey
+            }
+            SdkRecoveryError::SecretStorage(SecretStorageError::MissingKeyInfo { .. }) => {
+                RecoveryErrorKind::NoBackup
+            }
+            _ => RecoveryErrorKind::Other,
+        };
+        return Err(RecoveryError { kind, message: e.to_string() });
+    }
+
+    // secret storage doesn't have to come with a key backup, then there are no room keys to fetch
+    let backup_exists = backups.exists_on_server().await.map_err(|e| other(e.to_string()))?;
+    if !backup_exists {
+        debug!("Recovered secrets, but there is no key backup to download room keys from");
+        emit_progress(&app_handle, RecoveryProgress::Done);
+        return Ok(recovery.state().into());
+    }
+
+    let encrypted_rooms: Vec<_> = client
+        .joined_rooms()
+        .into_iter()
+        .filter(|room| room.encryption_state().is_encrypted())
+        .collect();
+    let total = encrypted_rooms.len();
+    for (index, room) in encrypted_rooms.iter().enumerate() {
+        emit_progress(&app_handle, RecoveryProgress::DownloadingKeys { current: index, total });
+        // a room failing shouldn't stop the others, its keys can still be fetched on demand later
+        if let Err(e) = backups.download_room_keys_for_room(room.room_id()).await {
+            error!("Failed to download room keys for {}: {}", room.room_id(), e);
+        }
+    }
+    emit_progress(&app_handle, RecoveryProgress::Done);
+
+    Ok(recovery.state().into())
+}
+
+/// Cancel an identity reset that is waiting for approval on the server's account management page.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client the reset was started on.
+#[tauri::command]
+pub async fn cancel_identity_reset(
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    client_handler
+        .cancel_identity_reset()
+        .await
+        .map_err(|e| format!("Failed to cancel identity reset: {}", e))?;
+    Ok("identity reset cancelled".into())
+}
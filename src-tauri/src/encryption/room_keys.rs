@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+use tauri::{AppHandle, Emitter, State};
+use tracing::{debug, error};
+use crate::encryption::encryption_types::{KeyImportProgress, KeyImportResult};
+use crate::ClientState;
+
+fn emit_progress(app_handle: &AppHandle, progress: KeyImportProgress) {
+    if let Err(e) = app_handle.emit("room_keys:import_progress", progress) {
+        error!("Failed to emit key import progress: {}", e);
+    }
+}
+
+/// Export the room keys to a file in the standard encrypted key export format, the same one
+/// Element and other clients use, so it can be imported there (or back here) later.
+///
+/// # Arguments
+/// * `path` - Where to write the key file.
+/// * `passphrase` - The passphrase to encrypt the file with.
+/// * `room_filter` - Only export the keys of these room IDs, or everything if `None`.
+/// * `state` - The client state containing the Matrix client to export the keys from.
+#[tauri::command]
+pub async fn export_room_keys(
+    path: String,
+    passphrase: String,
+    room_filter: Option<Vec<String>>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    client_handler
+        .get_client()
+        .encryption()
+        .export_room_keys(PathBuf::from(path), &passphrase, |session| {
+            room_filter
+                .as_ref()
+                .is_none_or(|rooms| rooms.iter().any(|room_id| room_id == session.room_id().as_str()))
+        })
+        .await
+        .map_err(|e| format!("Failed to export room keys: {}", e))?;
+
+    Ok("room keys exported".into())
+}
+
+/// Import room keys from a file in the standard encrypted key export format, e.g. one exported
+/// from Element. Progress is reported through `room_keys:import_progress` events.
+///
+/// # Arguments
+/// * `path` - The key file to import.
+/// * `passphrase` - The passphrase the file was encrypted with.
+/// * `state` - The client state containing the Matrix client to import the keys into.
+/// * `app_handle` - The app handle used to emit the progress events.
+#[tauri::command]
+pub async fn import_room_keys(
+    path: String,
+    passphrase: String,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<KeyImportResult, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    emit_progress(&app_handle, KeyImportProgress::Importing);
+    let result = client_handler
+        .get_client()
+        .encryption()
+        .import_room_keys(PathBuf::from(path), &passphrase)
+        .await
+        .map_err(|e| format!("Failed to import room keys: {}", e))?;
+    debug!("Imported {} of {} room keys", result.imported_count, result.total_count);
+
+    let rooms: Vec<String> = result.keys.keys().map(|room_id| room_id.to_string()).collect();
+    emit_progress(&app_handle, KeyImportProgress::RetryingDecryption { rooms: rooms.len() });
+    // imported keys go through the sdk's room key stream, so the UtdTracker retries the events
+    // waiting for them on its own, this just lets the frontend know which rooms are affected
+    if let Err(e) = app_handle.emit("room_keys:received", &rooms) {
+        error!("Failed to emit received room keys: {}", e);
+    }
+    emit_progress(&app_handle, KeyImportProgress::Done);
+
+    Ok(KeyImportResult {
+        imported: result.imported_count,
+        skipped: result.total_count - result.imported_count,
+        rooms,
+    })
+}
@@ -0,0 +1,133 @@
+use futures_util::StreamExt;
+use matrix_sdk::{Client, Room, RoomMemberships};
+use ruma::OwnedUserId;
+use tauri::{AppHandle, Emitter, State};
+use tracing::{debug, error};
+use crate::encryption::encryption_types::IdentityChangedPayload;
+use crate::settings::account_settings::AccountSettings;
+use crate::ClientState;
+
+/// Refuse to send to `room` if it is set to require verified devices and some member has a device
+/// they haven't verified. The account-wide key sharing policy is enforced by the sdk itself, this
+/// covers the stricter per-room setting.
+///
+/// # Arguments
+/// * `client` - The Matrix client about to send.
+/// * `room` - The room to send to.
+/// * `app_handle` - The app handle, used to look up the account settings.
+pub async fn check_room_policy(client: &Client, room: &Room, app_handle: &AppHandle) -> anyhow::Result<()> {
+    let user_id = client.user_id().ok_or_else(|| anyhow::anyhow!("Not logged in"))?;
+    let settings = AccountSettings::load(app_handle, user_id.as_str())?;
+    let required = settings
+        .room_require_verified_devices
+        .get(room.room_id().as_str())
+        .copied()
+        .unwrap_or(false);
+    if !required || !room.encryption_state().is_encrypted() {
+        return Ok(());
+    }
+
+    for member in room.members(RoomMemberships::ACTIVE).await? {
+        let devices = client.encryption().get_user_devices(member.user_id()).await?;
+        if devices.devices().any(|device| !device.is_verified()) {
+            return Err(anyhow::anyhow!(
+                "{} has unverified devices, and this room only allows verified devices",
+                member.user_id()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Emit an `identity:changed` event whenever a user we pinned or verified changes their identity.
+///
+/// # Arguments
+/// * `client` - The Matrix client to watch.
+/// * `app_handle` - The app handle used to emit the events.
+pub async fn watch_identity_changes(client: Client, app_handle: AppHandle) {
+    let mut updates = match client.encryption().user_identities_stream().await {
+        Ok(updates) => updates,
+        Err(e) => {
+            error!("Failed to watch user identities: {}", e);
+            return;
+        }
+    };
+
+    while let Some(update) = updates.next().await {
+        for (user_id, identity) in update.changed {
+            let was_verified = identity.has_verification_violation();
+            if !was_verified && !identity.identity_needs_user_approval() {
+                continue;
+            }
+
+            debug!("Identity of {} changed (was verified: {})", user_id, was_verified);
+            let payload = IdentityChangedPayload { user_id: user_id.to_string(), was_verified };
+            if let Err(e) = app_handle.emit("identity:changed", payload) {
+                error!("Failed to emit identity change: {}", e);
+            }
+        }
+    }
+}
+
+/// Accept a user's new identity without verifying it, which unblocks sending to them again. To
+/// re-verify instead, use [`crate::encryption::verification::request_verification`].
+///
+/// # Arguments
+/// * `user_id` - The user whose identity changed.
+/// * `state` - The client state containing the Matrix client to accept the change with.
+#[tauri::command]
+pub async fn accept_identity_change(
+    user_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    let identity = client_handler
+        .get_client()
+        .encryption()
+        .get_user_identity(&user_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("User has no cross-signing identity")?;
+
+    if identity.has_verification_violation() {
+        identity.withdraw_verification().await.map_err(|e| e.to_string())?;
+    } else {
+        identity.pin().await.map_err(|e| e.to_string())?;
+    }
+    Ok("identity change accepted".into())
+}
+
+/// Override whether a room only allows sending while every member's devices are verified.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to change the setting for.
+/// * `enabled` - Whether verified devices are required, or `None` to remove the override.
+/// * `state` - The client state containing the Matrix client whose settings should be changed.
+/// * `app_handle` - The app handle used to access the settings store.
+#[tauri::command]
+pub async fn set_room_require_verified_devices(
+    room_id: String,
+    enabled: Option<bool>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let user_id = client_handler
+        .get_client()
+        .user_id()
+        .ok_or("Not logged in")?
+        .to_string();
+
+    let mut settings = AccountSettings::load(&app_handle, &user_id).map_err(|e| e.to_string())?;
+    match enabled {
+        Some(enabled) => settings.room_require_verified_devices.insert(room_id, enabled),
+        None => settings.room_require_verified_devices.remove(&room_id),
+    };
+    settings.save(&app_handle, &user_id).map_err(|e| e.to_string())?;
+
+    Ok("room trust setting saved".into())
+}
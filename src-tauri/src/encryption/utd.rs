@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use futures_util::StreamExt;
+use matrix_sdk::deserialized_responses::{TimelineEventKind, UnableToDecryptReason};
+use matrix_sdk::sync::SyncResponse;
+use matrix_sdk::{Client, Room};
+use ruma::events::room::encrypted::{EncryptedEventScheme, RoomEncryptedEventContent};
+use ruma::events::{AnySyncMessageLikeEvent, AnySyncTimelineEvent};
+use ruma::{OwnedEventId, OwnedRoomId, OwnedUserId};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Mutex;
+use tracing::{debug, error, trace};
+use crate::encryption::encryption_types::{UtdPayload, UtdReason, UtdRoomStats};
+use crate::events::client_events::ClientEvents;
+use crate::UtdState;
+
+impl From<&UnableToDecryptReason> for UtdReason {
+    fn from(reason: &UnableToDecryptReason) -> Self {
+        match reason {
+            UnableToDecryptReason::MissingMegolmSession { withheld_code: Some(_) } => UtdReason::Withheld,
+            UnableToDecryptReason::MissingMegolmSession { withheld_code: None }
+            | UnableToDecryptReason::UnknownMegolmMessageIndex => UtdReason::MissingKey,
+            UnableToDecryptReason::SenderIdentityNotTrusted(_) => UtdReason::UnverifiedSenderDevice,
+            UnableToDecryptReason::HistoricalMessageAndBackupIsDisabled
+            | UnableToDecryptReason::HistoricalMessageAndDeviceIsUnverified => UtdReason::Historical,
+            _ => UtdReason::Other,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct UtdResolvedPayload {
+    room_id: String,
+    event_id: String,
+}
+
+#[derive(Default)]
+struct RoomUtds {
+    /// Undecryptable events, with the megolm session they need.
+    pending: HashMap<OwnedEventId, Option<String>>,
+    resolved: usize,
+}
+
+/// Keeps track of the events we couldn't decrypt, so they can be retried once their room key shows
+/// up (from key backup, an import, or forwarded by another device).
+pub struct UtdTracker {
+    rooms: Mutex<HashMap<OwnedRoomId, RoomUtds>>,
+}
+
+/// Outcome of trying to decrypt an event again.
+enum Retry {
+    Decrypted(AnySyncTimelineEvent),
+    StillEncrypted(UtdReason),
+}
+
+impl UtdTracker {
+    pub fn new() -> Self {
+        UtdTracker {
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Ask the sdk for the event again, which decrypts it with whatever keys we have by now.
+    async fn retry(room: &Room, event_id: &OwnedEventId) -> anyhow::Result<Retry> {
+        let event = room.event(event_id, None).await?;
+        match event.kind {
+            TimelineEventKind::UnableToDecrypt { utd_info, .. } => {
+                Ok(Retry::StillEncrypted(UtdReason::from(&utd_info.reason)))
+            }
+            _ => Ok(Retry::Decrypted(event.raw().deserialize()?)),
+        }
+    }
+
+    /// Called by [`crate::sync_manager::SyncManager`] with every sync response. The sdk decrypts
+    /// what it can during sync and says why it couldn't decrypt the rest, each of those gets a
+    /// `matrix:utd` placeholder and is remembered for later.
+    ///
+    /// # Arguments
+    /// * `response` - The sync response.
+    /// * `app_handle` - The app handle used to emit the placeholders.
+    pub async fn on_sync_response(response: &SyncResponse, app_handle: &AppHandle) {
+        let tracker = &app_handle.state::<UtdState>().0;
+        for (room_id, update) in &response.rooms.joined {
+            for event in &update.timeline.events {
+                let TimelineEventKind::UnableToDecrypt { event, utd_info } = &event.kind else {
+                    continue;
+                };
+                let (Ok(Some(event_id)), Ok(Some(sender))) = (
+                    event.get_field::<OwnedEventId>("event_id"),
+                    event.get_field::<OwnedUserId>("sender"),
+                ) else {
+                    continue;
+                };
+                trace!("Received undecryptable event: {:?}", event_id);
+
+                let session_id = match event.get_field::<RoomEncryptedEventContent>("content") {
+                    Ok(Some(content)) => match content.scheme {
+                        EncryptedEventScheme::MegolmV1AesSha2(content) => Some(content.session_id),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                tracker
+                    .rooms
+                    .lock()
+                    .await
+                    .entry(room_id.clone())
+                    .or_default()
+                    .pending
+                    .insert(event_id.clone(), session_id);
+
+                let payload = UtdPayload {
+                    room_id: room_id.to_string(),
+                    event_id: event_id.to_string(),
+                    sender: sender.to_string(),
+                    reason: UtdReason::from(&utd_info.reason),
+                };
+                if let Err(e) = app_handle.emit("matrix:utd", payload) {
+                    error!("Failed to emit undecryptable event: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn emit_decrypted(event: AnySyncTimelineEvent, room: Room, app_handle: AppHandle) {
+        let payload = UtdResolvedPayload {
+            room_id: room.room_id().to_string(),
+            event_id: event.event_id().to_string(),
+        };
+        if let Err(e) = app_handle.emit("matrix:utd_resolved", payload) {
+            error!("Failed to emit resolved undecryptable event: {}", e);
+        }
+
+        if let AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(message)) = event {
+            ClientEvents::on_message(message, room, app_handle).await;
+        }
+    }
+
+    /// Retry the pending events that were waiting for one of the given megolm sessions.
+    async fn on_room_keys(&self, client: &Client, sessions: Vec<(OwnedRoomId, String)>, app_handle: &AppHandle) {
+        let candidates: Vec<(OwnedRoomId, OwnedEventId)> = {
+            let rooms = self.rooms.lock().await;
+            sessions
+                .iter()
+                .filter_map(|(room_id, session_id)| Some((room_id, session_id, rooms.get(room_id)?)))
+                .flat_map(|(room_id, session_id, utds)| {
+                    utds.pending
+                        .iter()
+                        .filter(move |(_, pending_session)| {
+                            pending_session.as_deref().is_none_or(|s| s == session_id)
+                        })
+                        .map(move |(event_id, _)| (room_id.clone(), event_id.clone()))
+                })
+                .collect()
+        };
+
+        for (room_id, event_id) in candidates {
+            let Some(room) = client.get_room(&room_id) else {
+                continue;
+            };
+            match Self::retry(&room, &event_id).await {
+                Ok(Retry::Decrypted(event)) => {
+                    debug!("Decrypted {} after its key arrived", event_id);
+                    if let Some(utds) = self.rooms.lock().await.get_mut(&room_id) {
+                        if utds.pending.remove(&event_id).is_some() {
+                            utds.resolved += 1;
+                        }
+                    }
+                    Self::emit_decrypted(event, room, app_handle.clone()).await;
+                }
+                Ok(Retry::StillEncrypted(_)) => {}
+                Err(e) => error!("Failed to retry decrypting {}: {}", event_id, e),
+            }
+        }
+    }
+
+    /// Listen for new room keys for as long as the client lives and retry the events that were
+    /// waiting for them.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client whose room keys should be watched.
+    /// * `app_handle` - The app handle used to emit the decrypted events.
+    pub async fn watch_room_keys(client: Client, app_handle: AppHandle) {
+        let Some(mut room_keys) = client.encryption().room_keys_received_stream().await else {
+            error!("Encryption isn't set up, can't watch for room keys");
+            return;
+        };
+
+        while let Some(keys) = room_keys.next().await {
+            let Ok(keys) = keys else {
+                continue;
+            };
+            let sessions = keys
+                .into_iter()
+                .map(|key| (key.room_id, key.session_id))
+                .collect();
+            app_handle
+                .state::<UtdState>()
+                .0
+                .on_room_keys(&client, sessions, &app_handle)
+                .await;
+        }
+    }
+
+    /// Forget every tracked event, used when the session ends.
+    pub async fn clear(&self) {
+        self.rooms.lock().await.clear();
+    }
+
+    /// Number of pending and resolved undecryptable events per room.
+    pub async fn stats(&self) -> HashMap<String, UtdRoomStats> {
+        self.rooms
+            .lock()
+            .await
+            .iter()
+            .map(|(room_id, utds)| {
+                (
+                    room_id.to_string(),
+                    UtdRoomStats { pending: utds.pending.len(), resolved: utds.resolved },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Get how many events failed to decrypt in each room, and how many of those got decrypted later.
+///
+/// # Arguments
+/// * `utd` - The state holding the undecryptable event tracker.
+#[tauri::command]
+pub async fn get_utd_diagnostics(
+    utd: State<'_, UtdState>,
+) -> Result<HashMap<String, UtdRoomStats>, String> {
+    Ok(utd.0.stats().await)
+}
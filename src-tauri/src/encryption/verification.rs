@@ -0,0 +1,385 @@
+use futures_util::StreamExt;
+use matrix_sdk::encryption::verification::{
+    QrVerification, QrVerificationData, QrVerificationState, SasState, SasVerification,
+    Verification, VerificationRequest, VerificationRequestState,
+};
+use matrix_sdk::Client;
+use ruma::{OwnedDeviceId, OwnedUserId};
+use tauri::{AppHandle, Emitter, State};
+use tracing::{debug, error};
+use crate::encryption::encryption_types::{
+    SasEmoji, SasPayload, VerificationMethod, VerificationRequestPayload, VerificationStage,
+    VerificationStatePayload,
+};
+use crate::ClientState;
+
+/// Called by [`crate::events::client_events::ClientEvents`] whenever a verification request
+/// arrives, either as a to-device event (our own devices) or in a DM (other users). Tells the
+/// frontend about it and starts following the flow.
+///
+/// # Arguments
+/// * `client` - The Matrix client the request was received on.
+/// * `user_id` - The user who sent the request.
+/// * `flow_id` - The transaction id (to-device) or event id (in-room) identifying the flow.
+/// * `device_id` - The device that sent the request, if known.
+/// * `app_handle` - The app handle used to emit the verification events.
+pub async fn on_verification_request(
+    client: Client,
+    user_id: OwnedUserId,
+    flow_id: String,
+    device_id: Option<OwnedDeviceId>,
+    app_handle: AppHandle,
+) {
+    let Some(request) = client
+        .encryption()
+        .get_verification_request(&user_id, &flow_id)
+        .await
+    else {
+        debug!("Verification request {} from {} is gone already", flow_id, user_id);
+        return;
+    };
+
+    let payload = VerificationRequestPayload {
+        flow_id,
+        user_id: user_id.to_string(),
+        device_id: device_id.map(|d| d.to_string()),
+        is_self_verification: request.is_self_verification(),
+    };
+    if let Err(e) = app_handle.emit("verification:request", payload) {
+        error!("Failed to emit verification request: {}", e);
+    }
+
+    watch_request(request, app_handle);
+}
+
+fn emit_stage(app_handle: &AppHandle, flow_id: &str, user_id: &str, stage: VerificationStage) {
+    let payload = VerificationStatePayload {
+        flow_id: flow_id.to_string(),
+        user_id: user_id.to_string(),
+        stage,
+    };
+    if let Err(e) = app_handle.emit("verification:state", payload) {
+        error!("Failed to emit verification state: {}", e);
+    }
+}
+
+/// Follow a verification request until it is done or cancelled, emitting its stages and handing
+/// over to [`watch_sas`] or [`watch_qr`] once a method has been picked.
+fn watch_request(request: VerificationRequest, app_handle: AppHandle) {
+    tokio::spawn(async move {
+        let flow_id = request.flow_id().as_str().to_string();
+        let user_id = request.other_user_id().to_string();
+        let mut changes = request.changes();
+
+        while let Some(state) = changes.next().await {
+            let stage = match state {
+                VerificationRequestState::Created { .. } | VerificationRequestState::Requested { .. } => {
+                    VerificationStage::Requested
+                }
+                VerificationRequestState::Ready { .. } => VerificationStage::Ready,
+                VerificationRequestState::Transitioned { verification } => match verification {
+                    Verification::SasV1(sas) => {
+                        watch_sas(sas, app_handle.clone());
+                        VerificationStage::Started { method: VerificationMethod::Sas }
+                    }
+                    Verification::QrV1(qr) => {
+                        watch_qr(qr, app_handle.clone());
+                        VerificationStage::Started { method: VerificationMethod::Qr }
+                    }
+                    _ => continue,
+                },
+                VerificationRequestState::Done => VerificationStage::Done,
+                VerificationRequestState::Cancelled(info) => VerificationStage::Cancelled {
+                    reason: info.reason().to_string(),
+                },
+            };
+
+            let finished = matches!(stage, VerificationStage::Done | VerificationStage::Cancelled { .. });
+            emit_stage(&app_handle, &flow_id, &user_id, stage);
+            if finished {
+                break;
+            }
+        }
+    });
+}
+
+/// Follow a SAS verification, pushing the emojis/decimals to the frontend once the keys are
+/// exchanged. A SAS started by the other side is accepted right away, since the user already
+/// accepted the request it belongs to.
+fn watch_sas(sas: SasVerification, app_handle: AppHandle) {
+    tokio::spawn(async move {
+        let flow_id = sas.flow_id().as_str().to_string();
+        let user_id = sas.other_user_id().to_string();
+        let mut changes = sas.changes();
+
+        while let Some(state) = changes.next().await {
+            match state {
+                SasState::Started { .. } if !sas.we_started() => {
+                    if let Err(e) = sas.accept().await {
+                        error!("Failed to accept SAS verification {}: {}", flow_id, e);
+                    }
+                }
+                SasState::KeysExchanged { .. } => {
+                    let payload = SasPayload {
+                        flow_id: flow_id.clone(),
+                        user_id: user_id.clone(),
+                        emojis: sas.emoji().map(|emojis| {
+                            emojis
+                                .iter()
+                                .map(|emoji| SasEmoji {
+                                    symbol: emoji.symbol.to_string(),
+                                    description: emoji.description.to_string(),
+                                })
+                                .collect()
+                        }),
+                        decimals: sas.decimals().unwrap_or_default(),
+                    };
+                    if let Err(e) = app_handle.emit("verification:sas", payload) {
+                        error!("Failed to emit SAS: {}", e);
+                    }
+                }
+                SasState::Done { .. } | SasState::Cancelled(_) => break,
+                _ => {}
+            }
+        }
+    });
+}
+
+/// Follow a QR verification, telling the frontend when the other side scanned our code so the
+/// user can confirm it.
+fn watch_qr(qr: QrVerification, app_handle: AppHandle) {
+    tokio::spawn(async move {
+        let flow_id = qr.flow_id().as_str().to_string();
+        let user_id = qr.other_user_id().to_string();
+        let mut changes = qr.changes();
+
+        while let Some(state) = changes.next().await {
+            match state {
+                QrVerificationState::Scanned => {
+                    emit_stage(&app_handle, &flow_id, &user_id, VerificationStage::QrScanned)
+                }
+                QrVerificationState::Done { .. } | QrVerificationState::Cancelled(_) => break,
+                _ => {}
+            }
+        }
+    });
+}
+
+async fn get_request(client: &Client, user_id: String, flow_id: &str) -> Result<VerificationRequest, String> {
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    client
+        .encryption()
+        .get_verification_request(&user_id, flow_id)
+        .await
+        .ok_or_else(|| "Verification request not found".to_string())
+}
+
+/// Ask a user (or one of their devices) to verify with us. Leaving `user_id` empty verifies our
+/// own identity with our other devices.
+///
+/// # Arguments
+/// * `user_id` - The user to verify, or `None` for self verification.
+/// * `device_id` - A specific device of that user to verify, or `None` to verify their identity.
+/// * `state` - The client state containing the Matrix client to verify with.
+/// * `app_handle` - The app handle used to emit the verification events.
+///
+/// ### Returns
+/// The flow id of the new request, used by the other verification commands.
+#[tauri::command]
+pub async fn request_verification(
+    user_id: Option<String>,
+    device_id: Option<String>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let encryption = client.encryption();
+
+    let user_id = match user_id {
+        Some(user_id) => OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?,
+        None => client.user_id().ok_or("Not logged in")?.to_owned(),
+    };
+
+    let request = match device_id {
+        Some(device_id) => {
+            let device_id = OwnedDeviceId::from(device_id);
+            let device = encryption
+                .get_device(&user_id, &device_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("Device not found")?;
+            device.request_verification().await.map_err(|e| e.to_string())?
+        }
+        None => {
+            let identity = encryption
+                .get_user_identity(&user_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("User has no cross-signing identity")?;
+            identity.request_verification().await.map_err(|e| e.to_string())?
+        }
+    };
+
+    let flow_id = request.flow_id().as_str().to_string();
+    watch_request(request, app_handle);
+    Ok(flow_id)
+}
+
+/// Accept an incoming verification request.
+///
+/// # Arguments
+/// * `user_id` - The user who sent the request.
+/// * `flow_id` - The flow id of the request.
+/// * `state` - The client state containing the Matrix client to verify with.
+#[tauri::command]
+pub async fn accept_verification(
+    user_id: String,
+    flow_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let request = get_request(client_handler.get_client(), user_id, &flow_id).await?;
+    request.accept().await.map_err(|e| e.to_string())?;
+    Ok("verification accepted".into())
+}
+
+/// Start emoji/decimal SAS verification on a request that is ready.
+///
+/// # Arguments
+/// * `user_id` - The other user of the verification.
+/// * `flow_id` - The flow id of the request.
+/// * `state` - The client state containing the Matrix client to verify with.
+#[tauri::command]
+pub async fn start_sas_verification(
+    user_id: String,
+    flow_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let request = get_request(client_handler.get_client(), user_id, &flow_id).await?;
+    request
+        .start_sas()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("The other side does not support SAS verification")?;
+    Ok("sas verification started".into())
+}
+
+/// Generate the QR code for a request that is ready, for the other device to scan.
+///
+/// # Arguments
+/// * `user_id` - The other user of the verification.
+/// * `flow_id` - The flow id of the request.
+/// * `state` - The client state containing the Matrix client to verify with.
+///
+/// ### Returns
+/// The raw bytes to encode in the QR code (in byte mode).
+#[tauri::command]
+pub async fn generate_verification_qr(
+    user_id: String,
+    flow_id: String,
+    state: State<'_, ClientState>,
+) -> Result<Vec<u8>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let request = get_request(client_handler.get_client(), user_id, &flow_id).await?;
+    let qr = request
+        .generate_qr_code()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("The other side can't scan QR codes")?;
+    qr.to_bytes().map_err(|e| e.to_string())
+}
+
+/// Hand the content of a QR code scanned from the other device over to the verification.
+///
+/// # Arguments
+/// * `user_id` - The other user of the verification.
+/// * `flow_id` - The flow id of the request.
+/// * `data` - The raw bytes decoded from the QR code.
+/// * `state` - The client state containing the Matrix client to verify with.
+#[tauri::command]
+pub async fn scan_verification_qr(
+    user_id: String,
+    flow_id: String,
+    data: Vec<u8>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let request = get_request(client_handler.get_client(), user_id, &flow_id).await?;
+    let data = QrVerificationData::from_bytes(data).map_err(|e| e.to_string())?;
+    request
+        .scan_qr_code(data)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Could not start QR verification")?;
+    Ok("qr code scanned".into())
+}
+
+/// Confirm that the emojis/decimals match, or that the other device scanned our QR code.
+///
+/// # Arguments
+/// * `user_id` - The other user of the verification.
+/// * `flow_id` - The flow id of the verification.
+/// * `state` - The client state containing the Matrix client to verify with.
+#[tauri::command]
+pub async fn confirm_verification(
+    user_id: String,
+    flow_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    match client_handler.get_client().encryption().get_verification(&user_id, &flow_id).await {
+        Some(Verification::SasV1(sas)) => sas.confirm().await.map_err(|e| e.to_string())?,
+        Some(Verification::QrV1(qr)) => qr.confirm().await.map_err(|e| e.to_string())?,
+        _ => return Err("Verification not found".to_string()),
+    }
+    Ok("verification confirmed".into())
+}
+
+/// Cancel a verification, either because the emojis/decimals didn't match or because the user
+/// declined it.
+///
+/// # Arguments
+/// * `user_id` - The other user of the verification.
+/// * `flow_id` - The flow id of the verification.
+/// * `mismatch` - Whether the cancellation is because the short auth strings didn't match.
+/// * `state` - The client state containing the Matrix client to verify with.
+#[tauri::command]
+pub async fn cancel_verification(
+    user_id: String,
+    flow_id: String,
+    mismatch: bool,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let encryption = client_handler.get_client().encryption();
+
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    match encryption.get_verification(&user_id, &flow_id).await {
+        Some(Verification::SasV1(sas)) if mismatch => sas.mismatch().await.map_err(|e| e.to_string())?,
+        Some(Verification::SasV1(sas)) => sas.cancel().await.map_err(|e| e.to_string())?,
+        Some(Verification::QrV1(qr)) => qr.cancel().await.map_err(|e| e.to_string())?,
+        _ => {
+            let request = encryption
+                .get_verification_request(&user_id, &flow_id)
+                .await
+                .ok_or("Verification not found")?;
+            request.cancel().await.map_err(|e| e.to_string())?;
+        }
+    }
+    Ok("verification cancelled".into())
+}
@@ -1,8 +1,17 @@
-use matrix_sdk::Room;
-use ruma::events::room::message::SyncRoomMessageEvent;
+use matrix_sdk::{Client, Room};
+use ruma::events::key::verification::request::ToDeviceKeyVerificationRequestEvent;
+use ruma::events::receipt::{ReceiptType, SyncReceiptEvent};
+use ruma::events::room::member::{OriginalSyncRoomMemberEvent, StrippedRoomMemberEvent};
+use ruma::events::room::message::{MessageType, OriginalSyncRoomMessageEvent, SyncRoomMessageEvent};
+use ruma::events::room::power_levels::SyncRoomPowerLevelsEvent;
 use tauri::{AppHandle, Emitter};
 use serde::Serialize;
 use tracing::{error, trace};
+use crate::encryption::verification::on_verification_request;
+use crate::profile::own_profile::on_member_event;
+use crate::rooms::invites::on_stripped_member_event;
+use crate::rooms::members::{on_membership_change, on_power_levels_change};
+use crate::rooms::receipts::ReadReceipt;
 
 pub struct ClientEvents;
 
@@ -14,17 +23,95 @@ struct MessagePayload {
     event_id: String,
 }
 
+#[derive(Clone, Serialize)]
+struct ReceiptPayload {
+    room_id: String,
+    event_id: String,
+    readers: Vec<ReadReceipt>,
+}
+
 impl ClientEvents {
     pub fn register_events(client: &matrix_sdk::Client, app_handle: AppHandle) {
+        let message_app = app_handle.clone();
         client.add_event_handler(move |event: SyncRoomMessageEvent, room: Room| {
-            let app = app_handle.clone();
+            let app = message_app.clone();
             async move {
                 Self::on_message(event, room, app).await;
             }
         });
+
+        let receipt_app = app_handle.clone();
+        client.add_event_handler(move |event: SyncReceiptEvent, room: Room| {
+            let app = receipt_app.clone();
+            async move {
+                Self::on_receipt(event, room, app).await;
+            }
+        });
+
+        let member_app = app_handle.clone();
+        client.add_event_handler(move |event: OriginalSyncRoomMemberEvent, room: Room, client: Client| {
+            let app = member_app.clone();
+            async move {
+                on_member_event(event, room, client, app).await;
+            }
+        });
+
+        let membership_app = app_handle.clone();
+        client.add_event_handler(move |event: OriginalSyncRoomMemberEvent, room: Room| {
+            let app = membership_app.clone();
+            async move {
+                on_membership_change(event, room, app).await;
+            }
+        });
+
+        let power_levels_app = app_handle.clone();
+        client.add_event_handler(move |_: SyncRoomPowerLevelsEvent, room: Room| {
+            let app = power_levels_app.clone();
+            async move {
+                on_power_levels_change(room, app).await;
+            }
+        });
+
+        // invites only come with stripped state
+        let invite_app = app_handle.clone();
+        client.add_event_handler(move |event: StrippedRoomMemberEvent, room: Room, client: Client| {
+            let app = invite_app.clone();
+            async move {
+                on_stripped_member_event(event, room, client, app).await;
+            }
+        });
+
+        // own devices ask for verification over to-device messages, other users in a DM
+        let verification_app = app_handle.clone();
+        client.add_event_handler(move |event: ToDeviceKeyVerificationRequestEvent, client: Client| {
+            let app = verification_app.clone();
+            async move {
+                on_verification_request(
+                    client,
+                    event.sender,
+                    event.content.transaction_id.to_string(),
+                    Some(event.content.from_device),
+                    app,
+                ).await;
+            }
+        });
+
+        let room_verification_app = app_handle.clone();
+        client.add_event_handler(move |event: OriginalSyncRoomMessageEvent, client: Client| {
+            let app = room_verification_app.clone();
+            async move {
+                // our own requests to someone else come back through sync as well
+                if client.user_id() == Some(&*event.sender) {
+                    return;
+                }
+                if let MessageType::VerificationRequest(_) = &event.content.msgtype {
+                    on_verification_request(client, event.sender, event.event_id.to_string(), None, app).await;
+                }
+            }
+        });
     }
 
-    async fn on_message(event: SyncRoomMessageEvent, room: Room, app_handle: AppHandle) {
+    pub(crate) async fn on_message(event: SyncRoomMessageEvent, room: Room, app_handle: AppHandle) {
         trace!("Received message: {:?}", event);
 
         // Get the content based on event type
@@ -59,5 +146,36 @@ impl ClientEvents {
             error!("Failed to emit message event: {}", e);
         }
     }
-}
 
+    async fn on_receipt(event: SyncReceiptEvent, room: Room, app_handle: AppHandle) {
+        trace!("Received receipts: {:?}", event);
+
+        // a single receipt event can move the read marker of several users to several events,
+        // group them per event so the frontend can update each message on its own
+        for (event_id, receipts) in event.content.0 {
+            let readers: Vec<ReadReceipt> = receipts
+                .into_iter()
+                .filter(|(receipt_type, _)| *receipt_type == ReceiptType::Read)
+                .flat_map(|(_, users)| users.into_iter())
+                .map(|(user_id, receipt)| ReadReceipt {
+                    user_id: user_id.to_string(),
+                    ts: receipt.ts.map(|ts| ts.get().into()),
+                })
+                .collect();
+
+            if readers.is_empty() {
+                continue;
+            }
+
+            let payload = ReceiptPayload {
+                room_id: room.room_id().to_string(),
+                event_id: event_id.to_string(),
+                readers,
+            };
+
+            if let Err(e) = app_handle.emit("matrix:receipt", payload) {
+                error!("Failed to emit receipt event: {}", e);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -39,6 +39,21 @@ impl KeyringClient {
         }
     }
 
+    /// Delete the password stored in the keyring under `account`, if there is one.
+    ///
+    /// # Arguments
+    /// * `account` - The keyring account name whose entry should be removed.
+    pub fn delete_password(&self, account: &str) -> Result<()> {
+        let entry = Entry::new(&self.service, account)?;
+        match entry.delete_credential() {
+            Ok(()) | Err(KeyringError::NoEntry) => Ok(()),
+            Err(e) => {
+                error!("Failed to delete password from keyring (account={account:?}): {e:?}");
+                Err(anyhow::anyhow!("Failed to delete password from keyring: {e}"))
+            }
+        }
+    }
+
     /// Build an [iota_stronghold::KeyProvider] for `account`, creating the
     /// keyring entry if it does not yet exist.
     pub fn key_provider(&self, account: &str) -> Result<KeyProvider> {
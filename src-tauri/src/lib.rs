@@ -2,29 +2,79 @@ use crate::user::{
     get_all_spaces_with_trees, get_dm_rooms, get_rooms, get_space_tree, get_spaces, login, logout,
     oauth_login, oauth_register, register, reset_account, restore_session,
 };
+use crate::account::account_management::{
+    add_3pid, change_password, deactivate_account, delete_3pid, get_3pids, request_3pid_token,
+    submit_3pid_token,
+};
+use crate::account::devices::{delete_devices, get_devices, rename_device};
+use crate::encryption::recovery::{
+    cancel_identity_reset, get_recovery_state, get_security_status, recover_with_key,
+    setup_recovery,
+};
+use crate::encryption::room_keys::{export_room_keys, import_room_keys};
+use crate::encryption::trust::{accept_identity_change, set_room_require_verified_devices};
+use crate::encryption::utd::get_utd_diagnostics;
+use crate::encryption::verification::{
+    accept_verification, cancel_verification, confirm_verification, generate_verification_qr,
+    request_verification, scan_verification_qr, start_sas_verification,
+};
+use crate::media::cache::{clear_media_cache, get_media_cache_size, handle_media_request, resolve_media, MEDIA_SCHEME};
+use crate::media::upload::{cancel_upload, send_attachment};
+use crate::messages::outbox::{cancel_send, retry_send, send_message};
+use crate::messages::url_preview::{get_url_preview, set_room_url_previews};
+use crate::profile::own_profile::{
+    get_own_profile, set_avatar, set_display_name, set_room_avatar, set_room_display_name,
+};
+use crate::profile::user_profiles::{get_user_profile, search_users};
+use crate::rooms::direct::{create_dm, repair_m_direct};
+use crate::rooms::invites::{accept_invite, decline_invite, get_invites};
+use crate::rooms::members::get_room_members;
+use crate::rooms::membership::{ban_user, invite_user, kick_user, leave_room, unban_user};
+use crate::rooms::power_levels::{get_power_levels, set_event_power_level, set_user_power_level};
+use crate::rooms::receipts::{get_event_receipts, mark_room_read};
+use crate::rooms::room_encryption::{enable_room_encryption, get_room_encryption, share_room_history};
+use crate::rooms::room_settings::{get_room_settings, update_room_settings};
+use crate::settings::account_settings::{get_account_settings, set_account_settings};
 use tauri::Manager;
 use tokio::runtime::Runtime;
 use tokio::sync::RwLock;
 
 mod account;
 mod client_handler;
+mod encryption;
 mod events;
 mod keyring_client;
+mod media;
+mod messages;
+mod profile;
 mod rooms;
 mod secret;
+mod settings;
 mod spaces;
 mod store;
 mod sync_manager;
 mod user;
 
 use client_handler::ClientHandler;
+use encryption::utd::UtdTracker;
 use keyring_client::KeyringClient;
+use media::cache::MediaCache;
+use media::upload::UploadManager;
+use messages::url_preview::PreviewCache;
+use rooms::invites::InviteInbox;
+use rooms::members::MemberListCache;
 use secret::SecretService;
 use store::EchelonStore;
 
 pub struct ClientState(pub RwLock<Option<ClientHandler>>);
 pub struct SecretState(SecretService);
 pub struct StoreState(EchelonStore);
+pub struct UploadState(UploadManager);
+pub struct MediaState(MediaCache);
+pub struct PreviewState(PreviewCache);
+pub struct UtdState(UtdTracker);
+pub struct MemberListState(MemberListCache);
+pub struct InviteState(InviteInbox);
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -32,6 +82,12 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_store::Builder::new().build())
         .plugin(tauri_plugin_opener::init())
+        .register_asynchronous_uri_scheme_protocol(MEDIA_SCHEME, |ctx, request, responder| {
+            let app_handle = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                responder.respond(handle_media_request(&app_handle, request).await);
+            });
+        })
         .setup(|app| {
 
             keyring::use_native_store(true)?;
@@ -88,6 +144,12 @@ pub fn run() {
             app.manage(client_state);
             app.manage(secret_state);
             app.manage(store_state);
+            app.manage(UploadState(UploadManager::new()));
+            app.manage(MediaState(MediaCache::new(app_data_dir.join("media_cache"))));
+            app.manage(PreviewState(PreviewCache::new()));
+            app.manage(UtdState(UtdTracker::new()));
+            app.manage(MemberListState(MemberListCache::new()));
+            app.manage(InviteState(InviteInbox::new()));
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -103,6 +165,73 @@ pub fn run() {
             get_all_spaces_with_trees,
             get_space_tree,
             get_dm_rooms,
+            get_account_settings,
+            set_account_settings,
+            mark_room_read,
+            get_event_receipts,
+            send_message,
+            cancel_send,
+            retry_send,
+            send_attachment,
+            cancel_upload,
+            resolve_media,
+            get_media_cache_size,
+            clear_media_cache,
+            get_url_preview,
+            set_room_url_previews,
+            request_verification,
+            accept_verification,
+            start_sas_verification,
+            generate_verification_qr,
+            scan_verification_qr,
+            confirm_verification,
+            cancel_verification,
+            get_security_status,
+            setup_recovery,
+            get_recovery_state,
+            recover_with_key,
+            cancel_identity_reset,
+            export_room_keys,
+            import_room_keys,
+            get_utd_diagnostics,
+            get_devices,
+            rename_device,
+            delete_devices,
+            accept_identity_change,
+            set_room_require_verified_devices,
+            get_room_encryption,
+            enable_room_encryption,
+            share_room_history,
+            change_password,
+            deactivate_account,
+            get_3pids,
+            request_3pid_token,
+            add_3pid,
+            delete_3pid,
+            submit_3pid_token,
+            get_own_profile,
+            set_display_name,
+            set_avatar,
+            set_room_display_name,
+            set_room_avatar,
+            get_user_profile,
+            search_users,
+            create_dm,
+            repair_m_direct,
+            get_invites,
+            accept_invite,
+            decline_invite,
+            invite_user,
+            kick_user,
+            ban_user,
+            unban_user,
+            leave_room,
+            get_room_settings,
+            update_room_settings,
+            get_power_levels,
+            set_user_power_level,
+            set_event_power_level,
+            get_room_members,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,3 @@
+pub(crate) mod cache;
+pub(crate) mod media_types;
+pub(crate) mod upload;
\ No newline at end of file
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::Result;
+use matrix_sdk::media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings};
+use ruma::events::room::{EncryptedFile, MediaSource};
+use ruma::{MxcUri, OwnedMxcUri, UInt};
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+use crate::{ClientState, MediaState};
+
+/// Name of the custom URI scheme the webview loads media through.
+pub const MEDIA_SCHEME: &str = "echelon-media";
+
+/// Default upper bound for the size of the on-disk media cache, in bytes.
+const DEFAULT_CACHE_LIMIT: u64 = 512 * 1024 * 1024;
+
+/// Size of the thumbnails requested for room and user avatars.
+pub const AVATAR_SIZE: u32 = 96;
+
+/// On-disk LRU cache of downloaded (and, for encrypted attachments, decrypted) media, served to the
+/// webview through the `echelon-media://` scheme.
+///
+/// Files are named after the blake3 hash of the mxc uri, the requested size and, for encrypted
+/// attachments, their encryption info, so a plain and an encrypted file never share an entry.
+/// Recency is tracked through their modification time so the cache survives restarts without a
+/// separate index.
+pub struct MediaCache {
+    cache_dir: PathBuf,
+    limit: u64,
+    /// Encryption info for encrypted attachments, keyed by their mxc uri. The uri alone is not
+    /// enough to decrypt them, so the frontend registers these through [`resolve_media`] first.
+    encrypted: Mutex<HashMap<OwnedMxcUri, EncryptedFile>>,
+    /// Mimetypes announced by the events the media was sent in, keyed by mxc uri, registered
+    /// through [`resolve_media`] as well.
+    mimetypes: Mutex<HashMap<OwnedMxcUri, String>>,
+    /// Serialises writes and evictions so two downloads don't evict each other's files.
+    write_lock: Mutex<()>,
+}
+
+impl MediaCache {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        MediaCache {
+            cache_dir,
+            limit: DEFAULT_CACHE_LIMIT,
+            encrypted: Mutex::new(HashMap::new()),
+            mimetypes: Mutex::new(HashMap::new()),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Build the URL the webview should use to load `mxc`, optionally as a thumbnail.
+    ///
+    /// # Arguments
+    /// * `mxc` - The mxc uri of the media.
+    /// * `thumbnail` - The `(width, height)` of the thumbnail to request, or `None` for the full file.
+    pub fn media_url(mxc: &MxcUri, thumbnail: Option<(u32, u32)>) -> String {
+        // windows and android only allow custom protocols through http://<scheme>.localhost
+        #[cfg(any(windows, target_os = "android"))]
+        let base = format!("http://{MEDIA_SCHEME}.localhost");
+        #[cfg(not(any(windows, target_os = "android")))]
+        let base = format!("{MEDIA_SCHEME}://localhost");
+
+        let path = mxc.as_str().trim_start_matches("mxc://");
+        match thumbnail {
+            Some((width, height)) => format!("{base}/{path}?width={width}&height={height}"),
+            None => format!("{base}/{path}"),
+        }
+    }
+
+    /// Shorthand for the avatar-sized thumbnail URL of `mxc`.
+    pub fn avatar_url(mxc: &MxcUri) -> String {
+        Self::media_url(mxc, Some((AVATAR_SIZE, AVATAR_SIZE)))
+    }
+
+    /// Remember how to decrypt an encrypted attachment so it can be served by its mxc uri.
+    pub async fn register_encrypted(&self, file: EncryptedFile) -> OwnedMxcUri {
+        let mxc = file.url.clone();
+        self.encrypted.lock().await.insert(mxc.clone(), file);
+        mxc
+    }
+
+    /// Remember the mimetype of `mxc`, so it can be served with the right `Content-Type`.
+    pub async fn register_mimetype(&self, mxc: OwnedMxcUri, mimetype: String) {
+        self.mimetypes.lock().await.insert(mxc, mimetype);
+    }
+
+    /// The `Content-Type` to serve `data` with. Thumbnails are whatever image format the server
+    /// picked, so the mimetype of the original file only applies to the full file.
+    ///
+    /// # Arguments
+    /// * `mxc` - The mxc uri of the media.
+    /// * `thumbnail` - Whether `data` is a thumbnail.
+    /// * `data` - The content of the media.
+    pub async fn content_type(&self, mxc: &MxcUri, thumbnail: bool, data: &[u8]) -> String {
+        // encrypted media is always served whole, see `get`
+        let thumbnail = thumbnail && !self.encrypted.lock().await.contains_key(mxc);
+        let registered = if thumbnail {
+            None
+        } else {
+            self.mimetypes.lock().await.get(mxc).cloned()
+        };
+        registered.unwrap_or_else(|| {
+            image::guess_format(data)
+                .map(|format| format.to_mime_type())
+                .unwrap_or("application/octet-stream")
+                .to_string()
+        })
+    }
+
+    fn cache_path(&self, mxc: &MxcUri, thumbnail: Option<(u32, u32)>, encrypted: Option<&EncryptedFile>) -> PathBuf {
+        let mut key = match thumbnail {
+            Some((width, height)) => format!("{mxc}|{width}x{height}"),
+            None => mxc.to_string(),
+        };
+        if let Some(file) = encrypted {
+            // the key and hashes tell two encryptions of the same upload apart
+            key.push_str("|encrypted|");
+            key.push_str(&serde_json::to_string(file).unwrap_or_default());
+        }
+        self.cache_dir.join(blake3::hash(key.as_bytes()).to_string())
+    }
+
+    /// Get the content of `mxc`, from the cache if present, otherwise downloading (and decrypting)
+    /// it through the sdk, which takes care of authenticated media.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client to download the media with.
+    /// * `mxc` - The mxc uri of the media.
+    /// * `thumbnail` - The `(width, height)` of the thumbnail to fetch, or `None` for the full file.
+    pub async fn get(
+        &self,
+        client: &matrix_sdk::Client,
+        mxc: OwnedMxcUri,
+        thumbnail: Option<(u32, u32)>,
+    ) -> Result<Vec<u8>> {
+        let encrypted = self.encrypted.lock().await.get(&mxc).cloned();
+        // servers can't thumbnail encrypted media, so those are always fetched whole
+        let thumbnail = if encrypted.is_some() { None } else { thumbnail };
+
+        let path = self.cache_path(&mxc, thumbnail, encrypted.as_ref());
+        if let Ok(data) = tokio::fs::read(&path).await {
+            // bump the file so it counts as recently used, tokio has no async way of doing that
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = File::options().write(true).open(&path).and_then(|f| f.set_modified(SystemTime::now())) {
+                    debug!("Failed to touch cached media {}: {}", path.display(), e);
+                }
+            });
+            return Ok(data);
+        }
+
+        let source = match encrypted {
+            Some(file) => MediaSource::Encrypted(Box::new(file)),
+            None => MediaSource::Plain(mxc.clone()),
+        };
+        let format = match thumbnail {
+            Some((width, height)) => MediaFormat::Thumbnail(MediaThumbnailSettings::new(
+                UInt::from(width),
+                UInt::from(height),
+            )),
+            None => MediaFormat::File,
+        };
+        let data = client
+            .media()
+            .get_media_content(&MediaRequestParameters { source, format }, false)
+            .await?;
+
+        // the media is here either way, failing to cache it only costs a download next time
+        if let Err(e) = self.store(&path, &data).await {
+            error!("Failed to cache media {}: {}", mxc, e);
+        }
+        Ok(data)
+    }
+
+    async fn store(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        tokio::fs::write(path, data).await?;
+        if let Err(e) = self.evict().await {
+            error!("Failed to evict media cache: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Delete the least recently used files until the cache fits in its size limit.
+    async fn evict(&self) -> Result<()> {
+        let mut entries: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+        let mut dir = tokio::fs::read_dir(&self.cache_dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if let Ok(modified) = metadata.modified() {
+                entries.push((entry.path(), metadata.len(), modified));
+            }
+        }
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        if total <= self.limit {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in entries {
+            if total <= self.limit {
+                break;
+            }
+            match tokio::fs::remove_file(&path).await {
+                // a file removed in the meantime frees its space all the same
+                Ok(()) => total -= size,
+                Err(e) if e.kind() == ErrorKind::NotFound => total -= size,
+                Err(e) => error!("Failed to evict cached media {}: {}", path.display(), e),
+            }
+        }
+        debug!("Evicted media cache down to {} bytes", total);
+        Ok(())
+    }
+
+    /// Total size of the cached files, in bytes.
+    pub async fn size(&self) -> Result<u64> {
+        let mut dir = match tokio::fs::read_dir(&self.cache_dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut total = 0;
+        while let Some(entry) = dir.next_entry().await? {
+            if let Ok(metadata) = entry.metadata().await {
+                total += metadata.len();
+            }
+        }
+        Ok(total)
+    }
+
+    /// Forget the encryption info and mimetypes registered through [`resolve_media`], so the keys
+    /// of an account that's no longer logged in don't stay in memory.
+    pub async fn forget_sources(&self) {
+        self.encrypted.lock().await.clear();
+        self.mimetypes.lock().await.clear();
+    }
+
+    /// Remove every cached file, along with the registered sources.
+    pub async fn clear(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        self.forget_sources().await;
+        match tokio::fs::remove_dir_all(&self.cache_dir).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Handle a request made by the webview to the `echelon-media://` scheme. The path is the
+/// `server/media_id` part of the mxc uri, with optional `width` and `height` query parameters
+/// for thumbnails.
+///
+/// # Arguments
+/// * `app_handle` - The app handle, used to get the client and the media cache.
+/// * `request` - The request made by the webview.
+pub async fn handle_media_request(app_handle: &AppHandle, request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let uri = request.uri();
+    let mxc = OwnedMxcUri::from(format!("mxc://{}", uri.path().trim_start_matches('/')));
+    if !mxc.is_valid() {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid media uri");
+    }
+
+    let query: HashMap<&str, &str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let thumbnail = match (query.get("width"), query.get("height")) {
+        (Some(width), Some(height)) => width.parse().ok().zip(height.parse().ok()),
+        _ => None,
+    };
+
+    let client = {
+        let state = app_handle.state::<ClientState>();
+        let state_r = state.0.read().await;
+        match state_r.as_ref() {
+            Some(client_handler) => client_handler.get_client().clone(),
+            None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not logged in"),
+        }
+    };
+
+    let media = app_handle.state::<MediaState>();
+    match media.0.get(&client, mxc.clone(), thumbnail).await {
+        Ok(data) => {
+            let content_type = media.0.content_type(&mxc, thumbnail.is_some(), &data).await;
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .body(data)
+                .unwrap()
+        }
+        Err(e) => {
+            error!("Failed to load media {}: {}", uri, e);
+            error_response(StatusCode::NOT_FOUND, &e.to_string())
+        }
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(message.as_bytes().to_vec())
+        .unwrap()
+}
+
+/// Turn a media source from an event (plain `url` or encrypted `file`) into a URL the webview can
+/// load. Encrypted sources are remembered so the scheme handler can decrypt them.
+///
+/// # Arguments
+/// * `source` - The media source, as found in the event content.
+/// * `mimetype` - The mimetype from the event's `info`, used as the `Content-Type` of the file.
+/// * `width` - The width of the thumbnail to load, if a thumbnail is wanted.
+/// * `height` - The height of the thumbnail to load, if a thumbnail is wanted.
+/// * `media` - The media state holding the cache.
+#[tauri::command]
+pub async fn resolve_media(
+    source: MediaSource,
+    mimetype: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    media: State<'_, MediaState>,
+) -> Result<String, String> {
+    let mxc = match source {
+        MediaSource::Plain(mxc) => mxc,
+        MediaSource::Encrypted(file) => media.0.register_encrypted(*file).await,
+    };
+    if let Some(mimetype) = mimetype {
+        media.0.register_mimetype(mxc.clone(), mimetype).await;
+    }
+    Ok(MediaCache::media_url(&mxc, width.zip(height)))
+}
+
+/// Get the size of the on-disk media cache, in bytes.
+#[tauri::command]
+pub async fn get_media_cache_size(media: State<'_, MediaState>) -> Result<u64, String> {
+    media.0.size().await.map_err(|e| e.to_string())
+}
+
+/// Delete everything from the on-disk media cache.
+#[tauri::command]
+pub async fn clear_media_cache(media: State<'_, MediaState>) -> Result<String, String> {
+    media.0.clear().await.map_err(|e| e.to_string())?;
+    Ok("media cache cleared".into())
+}
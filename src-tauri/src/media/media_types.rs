@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// Progress of an attachment upload, emitted as `upload:progress` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub upload_id: String,
+    pub room_id: String,
+    pub current: usize,
+    pub total: usize,
+}
+
+/// Final outcome of an attachment upload, emitted as `upload:finished` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum UploadResult {
+    Sent { event_id: String },
+    Failed { reason: String },
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadFinished {
+    pub upload_id: String,
+    pub room_id: String,
+    #[serde(flatten)]
+    pub result: UploadResult,
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use futures_util::StreamExt;
+use image::{DynamicImage, ImageFormat};
+use matrix_sdk::attachment::{
+    AttachmentConfig, AttachmentInfo, BaseAudioInfo, BaseFileInfo, BaseImageInfo, BaseVideoInfo,
+    Thumbnail,
+};
+use matrix_sdk::{Client, Room};
+use mime_guess::mime::{self, Mime};
+use ruma::events::room::message::TextMessageEventContent;
+use ruma::{OwnedMxcUri, OwnedRoomId, TransactionId, UInt};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+use crate::encryption::trust::check_room_policy;
+use crate::media::media_types::{UploadFinished, UploadProgress, UploadResult};
+use crate::{ClientState, UploadState};
+
+/// Longest edge, in pixels, of the thumbnails generated for image attachments.
+const THUMBNAIL_SIZE: u32 = 800;
+
+/// Everything needed to send a file, worked out on a blocking thread before the upload starts.
+struct PreparedAttachment {
+    filename: String,
+    mime: Mime,
+    data: Vec<u8>,
+    info: AttachmentInfo,
+    thumbnail: Option<Thumbnail>,
+}
+
+/// Keeps track of the uploads in flight so they can be cancelled.
+pub struct UploadManager {
+    uploads: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl UploadManager {
+    pub fn new() -> Self {
+        UploadManager {
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start uploading `path` to `room` in the background, returning an id that identifies the
+    /// upload in the `upload:progress` and `upload:finished` events.
+    ///
+    /// # Arguments
+    /// * `room` - The room to send the attachment to.
+    /// * `path` - The path of the file to upload.
+    /// * `caption` - An optional caption to send along with the file.
+    /// * `app_handle` - The app handle used to emit the upload events.
+    pub async fn start(
+        &self,
+        room: Room,
+        path: PathBuf,
+        caption: Option<String>,
+        app_handle: AppHandle,
+    ) -> String {
+        let upload_id = TransactionId::new().to_string();
+
+        // hold the lock while spawning so the task can't try to remove itself before it's inserted
+        let mut uploads = self.uploads.lock().await;
+        let task_upload_id = upload_id.clone();
+        let handle = tokio::spawn(async move {
+            let room_id = room.room_id().to_owned();
+            let result = match Self::upload(&room, path, caption, &task_upload_id, &app_handle).await {
+                Ok(event_id) => UploadResult::Sent { event_id },
+                Err(e) => {
+                    error!("Upload {} failed: {}", task_upload_id, e);
+                    UploadResult::Failed { reason: e.to_string() }
+                }
+            };
+
+            app_handle.state::<UploadState>().0.uploads.lock().await.remove(&task_upload_id);
+            Self::emit_finished(&app_handle, &task_upload_id, &room_id, result);
+        });
+        uploads.insert(upload_id.clone(), handle);
+
+        upload_id
+    }
+
+    /// Abort an upload that is still in flight.
+    ///
+    /// # Arguments
+    /// * `upload_id` - The id returned by [`UploadManager::start`].
+    /// * `room_id` - The room the attachment was being sent to.
+    /// * `app_handle` - The app handle used to emit the cancellation event.
+    pub async fn cancel(&self, upload_id: &str, room_id: &OwnedRoomId, app_handle: &AppHandle) -> anyhow::Result<()> {
+        let handle = self
+            .uploads
+            .lock()
+            .await
+            .remove(upload_id)
+            .ok_or_else(|| anyhow::anyhow!("No upload in progress with that id"))?;
+
+        // dropping the upload future cancels the request mid-flight
+        handle.abort();
+        Self::emit_finished(app_handle, upload_id, room_id, UploadResult::Cancelled);
+        Ok(())
+    }
+
+    async fn upload(
+        room: &Room,
+        path: PathBuf,
+        caption: Option<String>,
+        upload_id: &str,
+        app_handle: &AppHandle,
+    ) -> anyhow::Result<String> {
+        let prepared = tokio::task::spawn_blocking(move || Self::prepare(&path)).await??;
+        debug!("Uploading {} ({}, {} bytes)", prepared.filename, prepared.mime, prepared.data.len());
+
+        let config = AttachmentConfig::new()
+            .info(prepared.info)
+            .thumbnail(prepared.thumbnail)
+            .caption(caption.map(TextMessageEventContent::plain));
+
+        // the sdk encrypts the file itself when the room is encrypted
+        let request = room.send_attachment(&prepared.filename, &prepared.mime, prepared.data, config);
+
+        let mut progress = request.subscribe_to_send_progress();
+        let progress_app = app_handle.clone();
+        let progress_id = upload_id.to_string();
+        let room_id = room.room_id().to_string();
+        let progress_task = tokio::spawn(async move {
+            while let Some(progress) = progress.next().await {
+                let payload = UploadProgress {
+                    upload_id: progress_id.clone(),
+                    room_id: room_id.clone(),
+                    current: progress.current,
+                    total: progress.total,
+                };
+                if let Err(e) = progress_app.emit("upload:progress", payload) {
+                    error!("Failed to emit upload progress: {}", e);
+                }
+            }
+        });
+
+        let result = request.await;
+        progress_task.abort();
+
+        Ok(result?.event_id.to_string())
+    }
+
+    /// Upload a file to the media repository without sending it anywhere, e.g. to use it as an
+    /// avatar. Goes through the same preparation as attachments, so the mimetype is guessed the
+    /// same way.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client to upload with.
+    /// * `path` - The path of the file to upload.
+    ///
+    /// ### Returns
+    /// The `mxc://` URI of the uploaded file and its mimetype.
+    pub async fn upload_media(client: &Client, path: PathBuf) -> anyhow::Result<(OwnedMxcUri, Mime)> {
+        let prepared = tokio::task::spawn_blocking(move || Self::prepare(&path)).await??;
+        debug!("Uploading {} ({}, {} bytes) to the media repository", prepared.filename, prepared.mime, prepared.data.len());
+
+        let response = client.media().upload(&prepared.mime, prepared.data, None).await?;
+        Ok((response.content_uri, prepared.mime))
+    }
+
+    /// Read the file and work out its mimetype and metadata. Images additionally get their
+    /// dimensions, a JPEG thumbnail and a blurhash.
+    fn prepare(path: &Path) -> anyhow::Result<PreparedAttachment> {
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?
+            .to_string();
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let data = std::fs::read(path)?;
+        let size = UInt::new(data.len() as u64);
+
+        let (info, thumbnail) = match mime.type_() {
+            mime::IMAGE => match image::load_from_memory(&data) {
+                Ok(image) => {
+                    let info = BaseImageInfo {
+                        width: Some(UInt::from(image.width())),
+                        height: Some(UInt::from(image.height())),
+                        size,
+                        blurhash: Self::blurhash(&image),
+                        ..Default::default()
+                    };
+                    // the thumbnail would only scale small images up, clients show those as they are
+                    let thumbnail = if image.width() <= THUMBNAIL_SIZE && image.height() <= THUMBNAIL_SIZE {
+                        None
+                    } else {
+                        Self::thumbnail(&image).ok()
+                    };
+                    (AttachmentInfo::Image(info), thumbnail)
+                }
+                Err(e) => {
+                    // not something we can decode, still send it but without the image metadata
+                    debug!("Could not decode image {}: {}", filename, e);
+                    (AttachmentInfo::Image(BaseImageInfo { size, ..Default::default() }), None)
+                }
+            },
+            mime::VIDEO => (AttachmentInfo::Video(BaseVideoInfo { size, ..Default::default() }), None),
+            mime::AUDIO => (AttachmentInfo::Audio(BaseAudioInfo { size, ..Default::default() }), None),
+            _ => (AttachmentInfo::File(BaseFileInfo { size }), None),
+        };
+
+        Ok(PreparedAttachment { filename, mime, data, info, thumbnail })
+    }
+
+    fn thumbnail(image: &DynamicImage) -> anyhow::Result<Thumbnail> {
+        let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+        let mut data = Vec::new();
+        thumbnail.write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)?;
+
+        Ok(Thumbnail {
+            width: UInt::from(thumbnail.width()),
+            height: UInt::from(thumbnail.height()),
+            size: UInt::new(data.len() as u64).unwrap_or_default(),
+            content_type: mime::IMAGE_JPEG,
+            data,
+        })
+    }
+
+    fn blurhash(image: &DynamicImage) -> Option<String> {
+        // the hash only encodes a handful of components, so a tiny copy of the image is plenty
+        let small = image.thumbnail(32, 32).to_rgba8();
+        blurhash::encode(4, 3, small.width(), small.height(), small.as_raw()).ok()
+    }
+
+    fn emit_finished(app_handle: &AppHandle, upload_id: &str, room_id: &OwnedRoomId, result: UploadResult) {
+        let payload = UploadFinished {
+            upload_id: upload_id.to_string(),
+            room_id: room_id.to_string(),
+            result,
+        };
+        if let Err(e) = app_handle.emit("upload:finished", payload) {
+            error!("Failed to emit upload result: {}", e);
+        }
+    }
+}
+
+/// Send a file (image, video, audio or anything else) to a room. The upload runs in the
+/// background, progress is reported through `upload:progress` events and the outcome through an
+/// `upload:finished` event, both carrying the returned upload id.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to send the file to.
+/// * `path` - The path of the file on disk.
+/// * `caption` - An optional caption to send with the file.
+/// * `state` - The client state containing the Matrix client to upload with.
+/// * `uploads` - The upload state keeping track of uploads in flight.
+/// * `app_handle` - The app handle used to emit the upload events.
+#[tauri::command]
+pub async fn send_attachment(
+    room_id: String,
+    path: String,
+    caption: Option<String>,
+    state: State<'_, ClientState>,
+    uploads: State<'_, UploadState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let room = {
+        let state_r = state.0.read().await;
+        let client_handler = state_r.as_ref().unwrap();
+        let client = client_handler.get_client();
+        let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+        let room = client.get_room(&room_id).ok_or("Room not found")?;
+        check_room_policy(client, &room, &app_handle)
+            .await
+            .map_err(|e| format!("Refusing to send: {}", e))?;
+        room
+    };
+
+    Ok(uploads.0.start(room, PathBuf::from(path), caption, app_handle).await)
+}
+
+/// Cancel an upload started with [`send_attachment`].
+///
+/// # Arguments
+/// * `upload_id` - The id returned by [`send_attachment`].
+/// * `room_id` - The ID of the room the file was being sent to.
+/// * `uploads` - The upload state keeping track of uploads in flight.
+/// * `app_handle` - The app handle used to emit the cancellation event.
+#[tauri::command]
+pub async fn cancel_upload(
+    upload_id: String,
+    room_id: String,
+    uploads: State<'_, UploadState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    uploads
+        .0
+        .cancel(&upload_id, &room_id, &app_handle)
+        .await
+        .map_err(|e| format!("Failed to cancel upload: {}", e))?;
+    Ok("upload cancelled".into())
+}
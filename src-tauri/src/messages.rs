@@ -0,0 +1,3 @@
+pub(crate) mod message_types;
+pub(crate) mod outbox;
+pub(crate) mod url_preview;
\ No newline at end of file
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// The state of a message in the outbox, emitted to the frontend as `outbox:update` events so it
+/// can render local echoes before the server has acknowledged them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum OutboxState {
+    /// The message was accepted into the queue (and persisted), but not sent yet.
+    Queued { body: Option<String> },
+    /// The message is being (re)sent to the homeserver.
+    Sending,
+    /// The homeserver accepted the message and assigned it an event id.
+    Sent { event_id: String },
+    /// Sending failed. If `recoverable` is true it will be retried automatically once the
+    /// connection is back, otherwise the user has to retry or cancel it.
+    Failed { reason: String, recoverable: bool },
+    /// The message was removed from the queue before it got sent.
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxUpdate {
+    pub room_id: String,
+    pub transaction_id: String,
+    #[serde(flatten)]
+    pub state: OutboxState,
+}
+
+/// OpenGraph data for a link, as returned by the homeserver's preview endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    /// `echelon-media://` URL of the preview image, if any.
+    pub image_url: Option<String>,
+    pub image_width: Option<u64>,
+    pub image_height: Option<u64>,
+}
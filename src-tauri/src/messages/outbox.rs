@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use matrix_sdk::send_queue::{LocalEchoContent, RoomSendQueueUpdate, SendHandle};
+use matrix_sdk::Client;
+use ruma::events::room::message::RoomMessageEventContent;
+use ruma::events::AnyMessageLikeEventContent;
+use ruma::{OwnedRoomId, OwnedTransactionId};
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+use crate::encryption::trust::check_room_policy;
+use crate::messages::message_types::{OutboxState, OutboxUpdate};
+use crate::ClientState;
+
+/// Upper bound for the delay between two attempts at re-enabling the send queue.
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// Exponential backoff state for re-enabling the send queue after a recoverable (network) error.
+#[derive(Default)]
+struct Backoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// Thin layer over matrix-sdk's send queue. The SDK already persists unsent events in the
+/// store and gives each of them a transaction id, this keeps track of their [`SendHandle`]s so
+/// they can be cancelled or retried, re-enables the queue when sync comes back after a network
+/// error, and forwards every state change to the frontend as `outbox:update` events.
+pub struct Outbox {
+    app_handle: AppHandle,
+    /// Handles of the unsent events, with the room they are queued in.
+    handles: Mutex<HashMap<OwnedTransactionId, (OwnedRoomId, SendHandle)>>,
+    backoff: Mutex<Backoff>,
+    listeners: RwLock<Vec<JoinHandle<()>>>,
+}
+
+impl Outbox {
+    pub fn new(app_handle: AppHandle) -> Self {
+        Outbox {
+            app_handle,
+            handles: Mutex::new(HashMap::new()),
+            backoff: Mutex::new(Backoff::default()),
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Start listening to send queue updates for `client`, then restart sending of anything that
+    /// was left unsent from a previous run.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client whose send queue should be watched.
+    pub async fn start(self: &Arc<Self>, client: &Client) {
+        self.stop().await;
+
+        let send_queue = client.send_queue();
+        let mut updates = send_queue.subscribe();
+        let mut errors = send_queue.subscribe_errors();
+
+        // the listeners only hold on to the outbox weakly, so they end with it
+        let outbox = Arc::downgrade(self);
+        let update_listener = tokio::spawn(async move {
+            loop {
+                let update = updates.recv().await;
+                let Some(outbox) = outbox.upgrade() else {
+                    break;
+                };
+                match update {
+                    Ok(update) => outbox.on_update(update.room_id, update.update).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Outbox lagged behind, skipped {} send queue updates", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let outbox = Arc::downgrade(self);
+        let error_listener = tokio::spawn(async move {
+            loop {
+                let room_error = errors.recv().await;
+                let Some(outbox) = outbox.upgrade() else {
+                    break;
+                };
+                match room_error {
+                    Ok(room_error) if room_error.is_recoverable => {
+                        // the sdk disabled the queue, schedule the next attempt at turning it back on
+                        let mut backoff = outbox.backoff.lock().await;
+                        backoff.failures += 1;
+                        let delay = 2u64.saturating_pow(backoff.failures).min(MAX_BACKOFF_SECS);
+                        backoff.retry_at = Some(Instant::now() + Duration::from_secs(delay));
+                        debug!(
+                            "Send queue disabled in room {}, retrying in {}s",
+                            room_error.room_id, delay
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        *self.listeners.write().await = vec![update_listener, error_listener];
+
+        // pick up the handles of events persisted by a previous run so they can be cancelled
+        // or retried as well, then tell the sdk to resume sending them
+        for room in client.joined_rooms() {
+            match room.send_queue().subscribe().await {
+                Ok((local_echoes, _)) => {
+                    for echo in local_echoes {
+                        if let LocalEchoContent::Event { send_handle, .. } = echo.content {
+                            self.handles
+                                .lock()
+                                .await
+                                .insert(echo.transaction_id, (room.room_id().to_owned(), send_handle));
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to load unsent events for room {}: {}", room.room_id(), e),
+            }
+        }
+        client.send_queue().respawn_tasks_for_rooms_with_unsent_requests().await;
+    }
+
+    /// Stop listening to send queue updates.
+    pub async fn stop(&self) {
+        for handle in self.listeners.write().await.drain(..) {
+            handle.abort();
+        }
+    }
+
+    /// Called by the [`crate::sync_manager::SyncManager`] after every successful sync. A working
+    /// sync means the network is back, so re-enable the send queue once the backoff has elapsed.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client that just synced.
+    pub async fn on_sync_success(&self, client: &Client) {
+        let send_queue = client.send_queue();
+        if send_queue.is_enabled() {
+            return;
+        }
+
+        let mut backoff = self.backoff.lock().await;
+        if backoff.retry_at.is_none_or(|retry_at| Instant::now() >= retry_at) {
+            debug!("Re-enabling send queue after {} failures", backoff.failures);
+            send_queue.set_enabled(true).await;
+            backoff.retry_at = None;
+        }
+    }
+
+    /// Queue a message for sending, returning its transaction id. Refused if the room's trust
+    /// settings don't allow sending, see [`check_room_policy`].
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client to send the message with.
+    /// * `room_id` - The ID of the room to send the message to.
+    /// * `content` - The content of the message to send.
+    pub async fn send(
+        &self,
+        client: &Client,
+        room_id: OwnedRoomId,
+        content: AnyMessageLikeEventContent,
+    ) -> anyhow::Result<OwnedTransactionId> {
+        let room = client
+            .get_room(&room_id)
+            .ok_or_else(|| anyhow::anyhow!("Room not found"))?;
+        check_room_policy(client, &room, &self.app_handle)
+            .await
+            .map_err(|e| anyhow::anyhow!("Refusing to send: {}", e))?;
+
+        let handle = room.send_queue().send(content).await?;
+        let transaction_id = handle.transaction_id().to_owned();
+        self.handles.lock().await.insert(transaction_id.clone(), (room_id, handle));
+
+        Ok(transaction_id)
+    }
+
+    /// Remove an unsent message from the queue.
+    ///
+    /// # Arguments
+    /// * `transaction_id` - The transaction id of the message to cancel.
+    pub async fn cancel(&self, transaction_id: &OwnedTransactionId) -> anyhow::Result<()> {
+        let (_, handle) = self.handle(transaction_id).await?;
+
+        // keep the handle until the abort went through, a message that is being sent right now
+        // can't be cancelled anymore but may still fail and need a retry
+        if !handle.abort().await? {
+            return Err(anyhow::anyhow!("Message was already sent"));
+        }
+        self.handles.lock().await.remove(transaction_id);
+        Ok(())
+    }
+
+    /// The room and send handle of an unsent message, cloned so the map isn't locked while
+    /// talking to the sdk.
+    async fn handle(&self, transaction_id: &OwnedTransactionId) -> anyhow::Result<(OwnedRoomId, SendHandle)> {
+        self.handles
+            .lock()
+            .await
+            .get(transaction_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No unsent message with that transaction id"))
+    }
+
+    /// Retry sending a message that failed with an unrecoverable error. The room's trust settings
+    /// are checked again, they may have changed since the message was queued.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client the message is queued on.
+    /// * `transaction_id` - The transaction id of the message to retry.
+    pub async fn retry(&self, client: &Client, transaction_id: &OwnedTransactionId) -> anyhow::Result<()> {
+        let (room_id, handle) = self.handle(transaction_id).await?;
+
+        let room = client
+            .get_room(&room_id)
+            .ok_or_else(|| anyhow::anyhow!("Room not found"))?;
+        check_room_policy(client, &room, &self.app_handle)
+            .await
+            .map_err(|e| anyhow::anyhow!("Refusing to send: {}", e))?;
+
+        handle.unwedge().await?;
+        Ok(())
+    }
+
+    async fn on_update(&self, room_id: OwnedRoomId, update: RoomSendQueueUpdate) {
+        let (transaction_id, state) = match update {
+            RoomSendQueueUpdate::NewLocalEvent(echo) => {
+                let LocalEchoContent::Event { serialized_event, send_handle, .. } = echo.content else {
+                    return;
+                };
+                let body = match serialized_event.deserialize() {
+                    Ok(AnyMessageLikeEventContent::RoomMessage(content)) => Some(content.body().to_string()),
+                    _ => None,
+                };
+                self.handles
+                    .lock()
+                    .await
+                    .insert(echo.transaction_id.clone(), (room_id.clone(), send_handle));
+                self.emit(&room_id, &echo.transaction_id, OutboxState::Queued { body });
+
+                // the sdk starts sending right away, unless the queue is paused after a network error
+                if self.backoff.lock().await.retry_at.is_some() {
+                    return;
+                }
+                (echo.transaction_id, OutboxState::Sending)
+            }
+            RoomSendQueueUpdate::RetryEvent { transaction_id } => (transaction_id, OutboxState::Sending),
+            RoomSendQueueUpdate::SentEvent { transaction_id, event_id } => {
+                self.handles.lock().await.remove(&transaction_id);
+                *self.backoff.lock().await = Backoff::default();
+                (transaction_id, OutboxState::Sent { event_id: event_id.to_string() })
+            }
+            RoomSendQueueUpdate::SendError { transaction_id, error, is_recoverable } => (
+                transaction_id,
+                OutboxState::Failed { reason: error.to_string(), recoverable: is_recoverable },
+            ),
+            RoomSendQueueUpdate::CancelledLocalEvent { transaction_id } => {
+                self.handles.lock().await.remove(&transaction_id);
+                (transaction_id, OutboxState::Cancelled)
+            }
+            _ => return,
+        };
+
+        self.emit(&room_id, &transaction_id, state);
+    }
+
+    fn emit(&self, room_id: &OwnedRoomId, transaction_id: &OwnedTransactionId, state: OutboxState) {
+        let payload = OutboxUpdate {
+            room_id: room_id.to_string(),
+            transaction_id: transaction_id.to_string(),
+            state,
+        };
+        if let Err(e) = self.app_handle.emit("outbox:update", payload) {
+            error!("Failed to emit outbox update: {}", e);
+        }
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        if let Ok(mut listeners) = self.listeners.try_write() {
+            for handle in listeners.drain(..) {
+                handle.abort();
+            }
+        }
+    }
+}
+
+/// Send a text message to a room. The message is queued (and persisted) first, its progress is
+/// reported through `outbox:update` events keyed by the returned transaction id.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to send the message to.
+/// * `body` - The markdown body of the message.
+/// * `state` - The client state containing the Matrix client to send the message with.
+#[tauri::command]
+pub async fn send_message(
+    room_id: String,
+    body: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let content = AnyMessageLikeEventContent::RoomMessage(RoomMessageEventContent::text_markdown(body));
+
+    client_handler
+        .outbox
+        .send(client, room_id, content)
+        .await
+        .map(|transaction_id| transaction_id.to_string())
+        .map_err(|e| format!("Failed to queue message: {}", e))
+}
+
+/// Cancel a message that has not been sent yet.
+///
+/// # Arguments
+/// * `transaction_id` - The transaction id returned by [`send_message`].
+/// * `state` - The client state containing the outbox the message is queued in.
+#[tauri::command]
+pub async fn cancel_send(
+    transaction_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    client_handler
+        .outbox
+        .cancel(&OwnedTransactionId::from(transaction_id))
+        .await
+        .map_err(|e| format!("Failed to cancel message: {}", e))?;
+    Ok("message cancelled".into())
+}
+
+/// Retry a message that failed to send.
+///
+/// # Arguments
+/// * `transaction_id` - The transaction id returned by [`send_message`].
+/// * `state` - The client state containing the outbox the message is queued in.
+#[tauri::command]
+pub async fn retry_send(
+    transaction_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    client_handler
+        .outbox
+        .retry(client_handler.get_client(), &OwnedTransactionId::from(transaction_id))
+        .await
+        .map_err(|e| format!("Failed to retry message: {}", e))?;
+    Ok("message retried".into())
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use matrix_sdk::Client;
+use ruma::api::client::authenticated_media::get_media_preview;
+use ruma::api::client::media::get_media_preview as legacy_get_media_preview;
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedMxcUri, OwnedRoomId, UInt};
+use serde_json::Value;
+use tauri::{AppHandle, State};
+use tokio::sync::Mutex;
+use tracing::debug;
+use crate::media::cache::MediaCache;
+use crate::messages::message_types::UrlPreview;
+use crate::settings::account_settings::AccountSettings;
+use crate::{ClientState, PreviewState};
+
+/// Upper bound for the number of cached link previews.
+const PREVIEW_CACHE_LIMIT: usize = 500;
+
+/// In-memory LRU cache of link previews, keyed by URL and the timestamp they were requested for.
+/// Previews rarely change and the same link tends to get rendered over and over while scrolling,
+/// so there is no point asking the server twice. Previews come from the account's homeserver, so
+/// the cache is cleared when the session ends.
+pub struct PreviewCache {
+    /// The previews, with when they were last used.
+    previews: Mutex<HashMap<(String, Option<u64>), (UrlPreview, Instant)>>,
+}
+
+impl PreviewCache {
+    pub fn new() -> Self {
+        PreviewCache {
+            previews: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the preview for `url`, asking the homeserver if it isn't cached yet.
+    ///
+    /// # Arguments
+    /// * `client` - The Matrix client to request the preview with.
+    /// * `url` - The URL to preview.
+    /// * `ts` - The timestamp of the message the link was posted in, so the server can return a
+    ///   preview of the page as it was back then.
+    pub async fn get(&self, client: &Client, url: String, ts: Option<u64>) -> anyhow::Result<UrlPreview> {
+        // the server previews the page as it was at `ts`, so that's part of the key
+        let key = (url, ts);
+        if let Some((preview, last_used)) = self.previews.lock().await.get_mut(&key) {
+            *last_used = Instant::now();
+            return Ok(preview.clone());
+        }
+
+        let ts = ts.and_then(UInt::new).map(MilliSecondsSinceUnixEpoch);
+        let data = Self::fetch(client, key.0.clone(), ts).await?;
+        let preview = Self::parse(key.0.clone(), data);
+
+        let mut previews = self.previews.lock().await;
+        previews.insert(key, (preview.clone(), Instant::now()));
+        // drop the least recently used preview once the cache is full
+        if previews.len() > PREVIEW_CACHE_LIMIT {
+            let oldest = previews
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                previews.remove(&oldest);
+            }
+        }
+        Ok(preview)
+    }
+
+    /// Forget every preview, used when the session ends.
+    pub async fn clear(&self) {
+        self.previews.lock().await.clear();
+    }
+
+    /// Request the OpenGraph data from the authenticated media endpoint, falling back to the legacy
+    /// one for servers that don't support authenticated media yet.
+    async fn fetch(client: &Client, url: String, ts: Option<MilliSecondsSinceUnixEpoch>) -> anyhow::Result<Value> {
+        let mut request = get_media_preview::v1::Request::new(url.clone());
+        request.ts = ts;
+        let data = match client.send(request).await {
+            Ok(response) => response.data,
+            Err(e) => {
+                debug!("Authenticated preview request failed, trying the legacy endpoint: {}", e);
+                let mut request = legacy_get_media_preview::v3::Request::new(url);
+                request.ts = ts;
+                client.send(request).await?.data
+            }
+        };
+
+        Ok(match data {
+            Some(raw) => serde_json::from_str(raw.get())?,
+            None => Value::Null,
+        })
+    }
+
+    fn parse(url: String, data: Value) -> UrlPreview {
+        let string = |key: &str| data.get(key).and_then(Value::as_str).map(str::to_string);
+        let number = |key: &str| data.get(key).and_then(Value::as_u64);
+
+        let image_url = string("og:image")
+            .map(OwnedMxcUri::from)
+            .filter(|mxc| mxc.is_valid())
+            .map(|mxc| MediaCache::media_url(&mxc, None));
+
+        UrlPreview {
+            url,
+            title: string("og:title"),
+            description: string("og:description"),
+            site_name: string("og:site_name"),
+            image_url,
+            image_width: number("og:image:width"),
+            image_height: number("og:image:height"),
+        }
+    }
+}
+
+/// Get a preview (title, description, image and site name) of a link posted in a room. The preview
+/// is fetched by the homeserver, so no request is made to the linked site from this device.
+/// Returns `None` when previews are disabled for the room, see
+/// [`AccountSettings::url_previews_enabled`].
+///
+/// # Arguments
+/// * `room_id` - The ID of the room the link was posted in.
+/// * `url` - The URL to preview.
+/// * `ts` - The timestamp (in milliseconds) of the message containing the link.
+/// * `state` - The client state containing the Matrix client to request the preview with.
+/// * `previews` - The preview state holding the cache.
+/// * `app_handle` - The app handle, used to look up the account settings.
+#[tauri::command]
+pub async fn get_url_preview(
+    room_id: String,
+    url: String,
+    ts: Option<u64>,
+    state: State<'_, ClientState>,
+    previews: State<'_, PreviewState>,
+    app_handle: AppHandle,
+) -> Result<Option<UrlPreview>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    let user_id = client.user_id().ok_or("Not logged in")?.to_string();
+    let settings = AccountSettings::load(&app_handle, &user_id).map_err(|e| e.to_string())?;
+    if !settings.url_previews_enabled(room_id.as_str(), room.encryption_state().is_encrypted()) {
+        return Ok(None);
+    }
+
+    previews
+        .0
+        .get(client, url, ts)
+        .await
+        .map(Some)
+        .map_err(|e| format!("Failed to get URL preview: {}", e))
+}
+
+/// Override whether link previews are shown in a specific room.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to change the setting for.
+/// * `enabled` - Whether previews are enabled, or `None` to go back to the account-wide default.
+/// * `state` - The client state containing the Matrix client whose settings should be changed.
+/// * `app_handle` - The app handle used to access the settings store.
+#[tauri::command]
+pub async fn set_room_url_previews(
+    room_id: String,
+    enabled: Option<bool>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let user_id = client_handler
+        .get_client()
+        .user_id()
+        .ok_or("Not logged in")?
+        .to_string();
+
+    let mut settings = AccountSettings::load(&app_handle, &user_id).map_err(|e| e.to_string())?;
+    match enabled {
+        Some(enabled) => settings.room_url_previews.insert(room_id, enabled),
+        None => settings.room_url_previews.remove(&room_id),
+    };
+    settings.save(&app_handle, &user_id).map_err(|e| e.to_string())?;
+
+    Ok("url preview setting saved".into())
+}
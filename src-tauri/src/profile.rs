@@ -0,0 +1,3 @@
+pub(crate) mod own_profile;
+pub(crate) mod profile_types;
+pub(crate) mod user_profiles;
\ No newline at end of file
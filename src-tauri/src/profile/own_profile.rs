@@ -0,0 +1,249 @@
+use std::path::PathBuf;
+use matrix_sdk::deserialized_responses::SyncOrStrippedState;
+use matrix_sdk::{Client, Room};
+use ruma::events::room::member::{MembershipState, OriginalSyncRoomMemberEvent, RoomMemberEventContent};
+use ruma::events::SyncStateEvent;
+use ruma::{OwnedMxcUri, OwnedRoomId};
+use tauri::{AppHandle, Emitter, State};
+use tracing::{debug, error};
+use crate::media::cache::MediaCache;
+use crate::media::upload::UploadManager;
+use crate::profile::profile_types::{OwnProfile, ProfileUpdatedPayload};
+use crate::ClientState;
+
+fn emit_profile_updated(app_handle: &AppHandle, payload: ProfileUpdatedPayload) {
+    if let Err(e) = app_handle.emit("profile:updated", payload) {
+        error!("Failed to emit profile update: {}", e);
+    }
+}
+
+/// Upload an avatar image, refusing anything that isn't an image.
+async fn upload_avatar(client: &Client, path: String) -> Result<OwnedMxcUri, String> {
+    let path = PathBuf::from(path);
+    if mime_guess::from_path(&path).first_or_octet_stream().type_() != mime_guess::mime::IMAGE {
+        return Err("Avatars have to be images".to_string());
+    }
+    let (mxc, _) = UploadManager::upload_media(client, path)
+        .await
+        .map_err(|e| format!("Failed to upload avatar: {}", e))?;
+    Ok(mxc)
+}
+
+/// Called by [`crate::events::client_events::ClientEvents`] for every `m.room.member` event, emits
+/// `profile:updated` when it's our own and we're still in the room. This is how changes made from
+/// other clients reach the UI.
+///
+/// # Arguments
+/// * `event` - The member event.
+/// * `room` - The room it was sent in.
+/// * `client` - The Matrix client that received it.
+/// * `app_handle` - The app handle used to emit the update.
+pub async fn on_member_event(event: OriginalSyncRoomMemberEvent, room: Room, client: Client, app_handle: AppHandle) {
+    if client.user_id() != Some(&*event.state_key) || event.content.membership != MembershipState::Join {
+        return;
+    }
+
+    // joins show up here too, only forward actual profile changes
+    if let Some(prev) = event.unsigned.prev_content.as_ref() {
+        if prev.membership == MembershipState::Join
+            && prev.displayname == event.content.displayname
+            && prev.avatar_url == event.content.avatar_url
+        {
+            return;
+        }
+    }
+
+    emit_profile_updated(&app_handle, ProfileUpdatedPayload {
+        room_id: Some(room.room_id().to_string()),
+        display_name: event.content.displayname,
+        avatar_url: event.content.avatar_url.as_deref().map(MediaCache::avatar_url),
+    });
+}
+
+/// Get the global display name and avatar of the logged in user.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client to look up.
+#[tauri::command]
+pub async fn get_own_profile(
+    state: State<'_, ClientState>,
+) -> Result<OwnProfile, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let account = client.account();
+
+    Ok(OwnProfile {
+        user_id: client.user_id().ok_or("Not logged in")?.to_string(),
+        display_name: account.get_display_name().await.map_err(|e| e.to_string())?,
+        avatar_url: account
+            .get_avatar_url()
+            .await
+            .map_err(|e| e.to_string())?
+            .as_deref()
+            .map(MediaCache::avatar_url),
+    })
+}
+
+/// Change the global display name. The server propagates it to every room that doesn't have a
+/// room-specific one.
+///
+/// # Arguments
+/// * `display_name` - The new display name, or `None` to remove it.
+/// * `state` - The client state containing the Matrix client to change the profile of.
+/// * `app_handle` - The app handle used to emit the update.
+#[tauri::command]
+pub async fn set_display_name(
+    display_name: Option<String>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let account = client_handler.get_client().account();
+
+    account
+        .set_display_name(display_name.as_deref())
+        .await
+        .map_err(|e| format!("Failed to set display name: {}", e))?;
+
+    let avatar_url = account.get_cached_avatar_url().await.ok().flatten();
+    emit_profile_updated(&app_handle, ProfileUpdatedPayload {
+        room_id: None,
+        display_name,
+        avatar_url: avatar_url.as_deref().map(MediaCache::avatar_url),
+    });
+    Ok("display name set".into())
+}
+
+/// Change the global avatar. The image is uploaded the same way as attachments.
+///
+/// # Arguments
+/// * `path` - The path of the image to use, or `None` to remove the avatar.
+/// * `state` - The client state containing the Matrix client to change the profile of.
+/// * `app_handle` - The app handle used to emit the update.
+#[tauri::command]
+pub async fn set_avatar(
+    path: Option<String>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let account = client.account();
+
+    let avatar_url = match path {
+        Some(path) => Some(upload_avatar(client, path).await?),
+        None => None,
+    };
+    account
+        .set_avatar_url(avatar_url.as_deref())
+        .await
+        .map_err(|e| format!("Failed to set avatar: {}", e))?;
+
+    let display_name = account.get_display_name().await.ok().flatten();
+    emit_profile_updated(&app_handle, ProfileUpdatedPayload {
+        room_id: None,
+        display_name,
+        avatar_url: avatar_url.as_deref().map(MediaCache::avatar_url),
+    });
+    Ok("avatar set".into())
+}
+
+/// Send our `m.room.member` event to `room` with the given display name and avatar. Everything
+/// else in the current event (e.g. `is_direct`) is kept.
+async fn set_room_member_profile(
+    client: &Client,
+    room: &Room,
+    display_name: Option<String>,
+    avatar_url: Option<OwnedMxcUri>,
+) -> Result<(), String> {
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let current = room
+        .get_state_event_static_for_key::<RoomMemberEventContent, _>(user_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .and_then(|raw| raw.deserialize().ok());
+    let mut content = match current {
+        Some(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) => event.content,
+        _ => RoomMemberEventContent::new(MembershipState::Join),
+    };
+    content.membership = MembershipState::Join;
+    content.displayname = display_name;
+    content.avatar_url = avatar_url;
+
+    debug!("Updating own member event in room {}", room.room_id());
+    room.send_state_event_for_key(user_id, content)
+        .await
+        .map_err(|e| format!("Failed to update room profile: {}", e))?;
+    Ok(())
+}
+
+/// Set a display name that only applies in one room, keeping the room avatar as is. The
+/// `profile:updated` event follows once the server echoes the member event back.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to set the display name in.
+/// * `display_name` - The room-specific display name, or `None` to go back to the global one.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn set_room_display_name(
+    room_id: String,
+    display_name: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let member = room.get_member_no_sync(user_id).await.map_err(|e| e.to_string())?;
+
+    let display_name = match display_name {
+        Some(display_name) => Some(display_name),
+        None => client.account().get_display_name().await.map_err(|e| e.to_string())?,
+    };
+    let avatar_url = member.as_ref().and_then(|m| m.avatar_url()).map(ToOwned::to_owned);
+
+    set_room_member_profile(client, &room, display_name, avatar_url).await?;
+    Ok("room display name set".into())
+}
+
+/// Set an avatar that only applies in one room, keeping the room display name as is. The
+/// `profile:updated` event follows once the server echoes the member event back.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to set the avatar in.
+/// * `path` - The path of the image to use, or `None` to go back to the global avatar.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn set_room_avatar(
+    room_id: String,
+    path: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let member = room.get_member_no_sync(user_id).await.map_err(|e| e.to_string())?;
+
+    let avatar_url = match path {
+        Some(path) => Some(upload_avatar(client, path).await?),
+        None => client.account().get_avatar_url().await.map_err(|e| e.to_string())?,
+    };
+    let display_name = member.as_ref().and_then(|m| m.display_name()).map(ToOwned::to_owned);
+
+    set_room_member_profile(client, &room, display_name, avatar_url).await?;
+    Ok("room avatar set".into())
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// The global profile of the logged in user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnProfile {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Option<String>,
+}
+
+/// Emitted as `profile:updated` whenever the user's own display name or avatar changes, either
+/// globally or in a single room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileUpdatedPayload {
+    /// The room the change applies to, or `None` for the global profile.
+    pub room_id: Option<String>,
+    pub display_name: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Option<String>,
+}
+
+/// Someone else's profile, as shown when clicking on their name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Option<String>,
+    /// `online`, `unavailable` or `offline`, or `None` if the server doesn't share presence.
+    pub presence: Option<String>,
+    pub status_msg: Option<String>,
+    /// Milliseconds since the user was last active.
+    pub last_active_ago: Option<u64>,
+    /// Whether we verified the user's cross-signing identity.
+    pub is_verified: bool,
+    pub device_count: usize,
+    pub is_ignored: bool,
+    /// IDs of the rooms both of us are joined to.
+    pub shared_rooms: Vec<String>,
+}
+
+/// A user found in the user directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSearchResult {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Option<String>,
+}
+
+/// Results of `search_users`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSearchResults {
+    pub results: Vec<UserSearchResult>,
+    /// Whether the server cut the results off at the limit.
+    pub limited: bool,
+}
@@ -0,0 +1,152 @@
+use matrix_sdk::Client;
+use ruma::api::client::membership::mutual_rooms;
+use ruma::api::client::presence::get_presence;
+use ruma::api::client::profile::{AvatarUrl, DisplayName};
+use ruma::events::room::member::MembershipState;
+use ruma::{OwnedRoomId, OwnedUserId, UserId};
+use tauri::State;
+use tracing::debug;
+use crate::media::cache::MediaCache;
+use crate::profile::profile_types::{UserProfile, UserSearchResult, UserSearchResults};
+use crate::ClientState;
+
+/// The rooms we share with `user_id`. Asks the server first (MSC2666), which also knows about
+/// rooms whose member list we haven't loaded, and falls back to the local member lists.
+///
+/// # Arguments
+/// * `client` - The Matrix client to look up with.
+/// * `user_id` - The other user.
+pub(crate) async fn shared_rooms(client: &Client, user_id: &UserId) -> Vec<OwnedRoomId> {
+    let mut rooms = Vec::new();
+    let mut batch_token = None;
+    loop {
+        let mut request = mutual_rooms::unstable::Request::new(user_id.to_owned());
+        request.batch_token = batch_token;
+        match client.send(request).await {
+            Ok(response) => {
+                rooms.extend(response.joined);
+                match response.next_batch_token {
+                    Some(token) => batch_token = Some(token),
+                    None => return rooms,
+                }
+            }
+            Err(e) => {
+                debug!("Server can't list mutual rooms, falling back to local members: {}", e);
+                break;
+            }
+        }
+    }
+
+    let mut rooms = Vec::new();
+    for room in client.joined_rooms() {
+        if let Ok(Some(member)) = room.get_member_no_sync(user_id).await {
+            if *member.membership() == MembershipState::Join {
+                rooms.push(room.room_id().to_owned());
+            }
+        }
+    }
+    rooms
+}
+
+/// Get everything the profile view shows about a user. Parts the server refuses to share (presence
+/// is often disabled) are left empty instead of failing the whole call.
+///
+/// # Arguments
+/// * `user_id` - The user to look up.
+/// * `state` - The client state containing the Matrix client to look up with.
+#[tauri::command]
+pub async fn get_user_profile(
+    user_id: String,
+    state: State<'_, ClientState>,
+) -> Result<UserProfile, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    let encryption = client.encryption();
+
+    let profile = client
+        .account()
+        .fetch_user_profile_of(&user_id)
+        .await
+        .map_err(|e| format!("Failed to get profile: {}", e))?;
+    let display_name = profile.get_static::<DisplayName>().ok().flatten();
+    let avatar_url = profile.get_static::<AvatarUrl>().ok().flatten();
+
+    let presence = match client.send(get_presence::v3::Request::new(user_id.clone())).await {
+        Ok(presence) => Some(presence),
+        Err(e) => {
+            debug!("No presence for {}: {}", user_id, e);
+            None
+        }
+    };
+
+    let is_verified = encryption
+        .get_user_identity(&user_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .is_some_and(|identity| identity.is_verified());
+    let device_count = encryption
+        .get_user_devices(&user_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .devices()
+        .count();
+
+    Ok(UserProfile {
+        user_id: user_id.to_string(),
+        display_name,
+        avatar_url: avatar_url.as_deref().map(MediaCache::avatar_url),
+        presence: presence.as_ref().map(|p| p.presence.to_string()),
+        status_msg: presence.as_ref().and_then(|p| p.status_msg.clone()),
+        last_active_ago: presence
+            .as_ref()
+            .and_then(|p| p.last_active_ago)
+            .map(|d| d.as_millis() as u64),
+        is_verified,
+        device_count,
+        is_ignored: client.is_user_ignored(&user_id).await,
+        shared_rooms: shared_rooms(client, &user_id)
+            .await
+            .into_iter()
+            .map(|room_id| room_id.to_string())
+            .collect(),
+    })
+}
+
+/// Search the homeserver's user directory, which matches on both user IDs and display names.
+/// Depending on the server, it only returns users sharing a room with us or in public rooms.
+///
+/// # Arguments
+/// * `term` - What to search for.
+/// * `limit` - The maximum number of results, defaults to 20.
+/// * `state` - The client state containing the Matrix client to search with.
+#[tauri::command]
+pub async fn search_users(
+    term: String,
+    limit: Option<u64>,
+    state: State<'_, ClientState>,
+) -> Result<UserSearchResults, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let response = client_handler
+        .get_client()
+        .search_users(&term, limit.unwrap_or(20))
+        .await
+        .map_err(|e| format!("Failed to search users: {}", e))?;
+
+    Ok(UserSearchResults {
+        results: response
+            .results
+            .into_iter()
+            .map(|user| UserSearchResult {
+                user_id: user.user_id.to_string(),
+                display_name: user.display_name,
+                avatar_url: user.avatar_url.as_deref().map(MediaCache::avatar_url),
+            })
+            .collect(),
+        limited: response.limited,
+    })
+}
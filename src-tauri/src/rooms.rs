@@ -1 +1,9 @@
+pub(crate) mod direct;
+pub(crate) mod invites;
+pub(crate) mod members;
+pub(crate) mod membership;
+pub(crate) mod power_levels;
+pub(crate) mod receipts;
+pub(crate) mod room_encryption;
+pub(crate) mod room_settings;
 pub(crate) mod room_types;
\ No newline at end of file
@@ -0,0 +1,184 @@
+use std::collections::{BTreeSet, HashSet};
+use matrix_sdk::deserialized_responses::SyncOrStrippedState;
+use matrix_sdk::{Client, Room, RoomMemberships, RoomState};
+use ruma::api::client::room::create_room;
+use ruma::api::client::room::create_room::v3::RoomPreset;
+use ruma::events::direct::{DirectEventContent, OwnedDirectUserIdentifier};
+use ruma::events::room::encryption::RoomEncryptionEventContent;
+use ruma::events::room::member::{MembershipState, RoomMemberEventContent};
+use ruma::events::{GlobalAccountDataEventType, InitialStateEvent, SyncStateEvent};
+use ruma::{OwnedRoomId, OwnedUserId};
+use tauri::State;
+use tracing::debug;
+use crate::rooms::room_types::DirectRepairReport;
+use crate::ClientState;
+
+/// Fetch the `m.direct` account data from the homeserver, or an empty map if it was never set.
+/// Asking the server instead of reading the synced copy keeps us from overwriting changes made by
+/// another client since our last sync.
+pub(crate) async fn load_m_direct(client: &Client) -> anyhow::Result<DirectEventContent> {
+    match client.account().fetch_account_data(GlobalAccountDataEventType::Direct).await? {
+        Some(raw) => Ok(serde_json::from_str(raw.json().get())?),
+        None => Ok(DirectEventContent::default()),
+    }
+}
+
+/// Find a joined DM with exactly these users, so starting a DM twice opens the same room.
+async fn find_existing_dm(client: &Client, user_ids: &BTreeSet<OwnedUserId>) -> Option<Room> {
+    for room in client.joined_rooms() {
+        if !room.is_direct().await.unwrap_or(false) {
+            continue;
+        }
+        let targets: BTreeSet<OwnedUserId> = room
+            .direct_targets()
+            .into_iter()
+            .filter_map(|target| target.as_user_id().map(ToOwned::to_owned))
+            .collect();
+        if &targets == user_ids {
+            return Some(room);
+        }
+    }
+    None
+}
+
+/// Start a direct chat with one or more users. Reuses the DM we already have with exactly these
+/// users, otherwise creates an encrypted room flagged as direct. Either way the room is recorded
+/// in `m.direct` for all of them.
+///
+/// # Arguments
+/// * `user_ids` - The users to chat with, without ourselves.
+/// * `state` - The client state containing the Matrix client to create the room with.
+///
+/// ### Returns
+/// The ID of the DM room.
+#[tauri::command]
+pub async fn create_dm(
+    user_ids: Vec<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let own_user_id = client.user_id().ok_or("Not logged in")?;
+    let user_ids = user_ids
+        .into_iter()
+        .map(OwnedUserId::try_from)
+        .collect::<Result<BTreeSet<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    if user_ids.is_empty() || user_ids.contains(own_user_id) {
+        return Err("A DM needs at least one other user, and not yourself".to_string());
+    }
+
+    let room_id = match find_existing_dm(client, &user_ids).await {
+        Some(room) => {
+            debug!("Reusing existing DM {}", room.room_id());
+            room.room_id().to_owned()
+        }
+        None => {
+            let mut request = create_room::v3::Request::new();
+            request.invite = user_ids.iter().cloned().collect();
+            request.is_direct = true;
+            request.preset = Some(RoomPreset::TrustedPrivateChat);
+            request.initial_state = vec![
+                InitialStateEvent::new(RoomEncryptionEventContent::with_recommended_defaults()).to_raw_any(),
+            ];
+
+            let room = client
+                .create_room(request)
+                .await
+                .map_err(|e| format!("Failed to create DM: {}", e))?;
+            debug!("Created DM {}", room.room_id());
+            room.room_id().to_owned()
+        }
+    };
+
+    // the sdk marks new DMs itself, but an older DM may be missing some of its users
+    let user_ids: Vec<OwnedUserId> = user_ids.into_iter().collect();
+    client
+        .account()
+        .mark_as_dm(&room_id, &user_ids)
+        .await
+        .map_err(|e| format!("Failed to update m.direct: {}", e))?;
+
+    Ok(room_id.to_string())
+}
+
+/// Reconcile `m.direct` with the rooms we're actually in: drop rooms we aren't joined to, and add
+/// back joined rooms we were invited to as a DM that went missing (e.g. overwritten by a client
+/// with an outdated copy).
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client whose `m.direct` should be repaired.
+#[tauri::command]
+pub async fn repair_m_direct(
+    state: State<'_, ClientState>,
+) -> Result<DirectRepairReport, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+    let own_user_id = client.user_id().ok_or("Not logged in")?;
+
+    let mut direct = load_m_direct(client).await.map_err(|e| e.to_string())?;
+    let mut report = DirectRepairReport { kept: 0, removed: 0, added: 0 };
+
+    let is_current = |room_id: &OwnedRoomId| {
+        client
+            .get_room(room_id)
+            .is_some_and(|room| matches!(room.state(), RoomState::Joined))
+    };
+    for rooms in direct.values_mut() {
+        let before = rooms.len();
+        rooms.retain(|room_id| is_current(room_id));
+        report.removed += before - rooms.len();
+    }
+    direct.retain(|_, rooms| !rooms.is_empty());
+
+    let listed: HashSet<OwnedRoomId> = direct.values().flatten().cloned().collect();
+    report.kept = listed.len();
+
+    for room in client.joined_rooms() {
+        if listed.contains(room.room_id()) {
+            continue;
+        }
+        // our invite to a DM carries is_direct, it's gone from the join event but kept as prev_content
+        let own_member = room
+            .get_state_event_static_for_key::<RoomMemberEventContent, _>(own_user_id)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|raw| raw.deserialize().ok());
+        let Some(SyncOrStrippedState::Sync(SyncStateEvent::Original(own_member))) = own_member else {
+            continue;
+        };
+        let was_direct_invite = own_member
+            .unsigned
+            .prev_content
+            .is_some_and(|prev| prev.membership == MembershipState::Invite && prev.is_direct == Some(true));
+        if !was_direct_invite {
+            continue;
+        }
+
+        let Ok(members) = room.members(RoomMemberships::ACTIVE).await else {
+            continue;
+        };
+        for member in members.iter().filter(|m| m.user_id() != own_user_id) {
+            direct
+                .entry(OwnedDirectUserIdentifier::from(member.user_id().to_owned()))
+                .or_default()
+                .push(room.room_id().to_owned());
+        }
+        report.added += 1;
+    }
+
+    if report.removed > 0 || report.added > 0 {
+        client
+            .account()
+            .set_account_data(direct)
+            .await
+            .map_err(|e| format!("Failed to update m.direct: {}", e))?;
+    }
+    debug!("Repaired m.direct: {:?}", report);
+
+    Ok(report)
+}
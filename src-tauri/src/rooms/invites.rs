@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use matrix_sdk::deserialized_responses::MemberEvent;
+use matrix_sdk::{Client, Room, RoomState};
+use ruma::events::room::member::{MembershipState, StrippedRoomMemberEvent};
+use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+use crate::media::cache::MediaCache;
+use crate::profile::user_profiles::shared_rooms;
+use crate::rooms::room_encryption::encryption_algorithm;
+use crate::rooms::room_types::{InviteInfo, RawRoom};
+use crate::settings::account_settings::AccountSettings;
+use crate::{ClientState, InviteState};
+
+/// Build the inbox entry for an invited room.
+///
+/// # Arguments
+/// * `room` - The room we were invited to.
+async fn invite_info(room: &Room) -> anyhow::Result<InviteInfo> {
+    let invite = room.invite_details().await?;
+    let ts = match invite.invitee.event().as_ref() {
+        MemberEvent::Sync(event) => Some(event.origin_server_ts().get().into()),
+        MemberEvent::Stripped(_) => None,
+    };
+    let is_direct = invite
+        .invitee
+        .event()
+        .original_content()
+        .and_then(|content| content.is_direct)
+        .unwrap_or(false);
+
+    Ok(InviteInfo {
+        base: RawRoom {
+            id: room.room_id().to_string(),
+            name: room.name(),
+            topic: room.topic(),
+            avatar_url: room.avatar_url().map(|m| MediaCache::avatar_url(&m)),
+            is_space: room.is_space(),
+            is_encrypted: room.encryption_state().is_encrypted(),
+            encryption_algorithm: encryption_algorithm(room),
+        },
+        inviter: invite.inviter.as_ref().map(|m| m.user_id().to_string()),
+        inviter_display_name: invite.inviter.as_ref().and_then(|m| m.display_name()).map(ToOwned::to_owned),
+        is_direct,
+        member_count: room.joined_members_count(),
+        ts,
+    })
+}
+
+/// Invite bookkeeping of the current account: which invites were already announced to the
+/// frontend, and which inviters we share no room with.
+pub struct InviteInbox {
+    announced: Mutex<HashSet<OwnedRoomId>>,
+    /// Whether an inviter is a stranger, so a spammer's invites don't each cost a request.
+    strangers: Mutex<HashMap<OwnedUserId, bool>>,
+}
+
+impl InviteInbox {
+    pub fn new() -> Self {
+        InviteInbox {
+            announced: Mutex::new(HashSet::new()),
+            strangers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remember that the invite to `room_id` is being announced. Returns `false` if it already
+    /// was, the stripped state of an invite shows up again e.g. with every full sync.
+    async fn announce(&self, client: &Client, room_id: &RoomId) -> bool {
+        let mut announced = self.announced.lock().await;
+        // forget the invites that were accepted or declined since, so a new invite gets through
+        announced.retain(|id| client.get_room(id).is_some_and(|room| matches!(room.state(), RoomState::Invited)));
+        announced.insert(room_id.to_owned())
+    }
+
+    /// Whether `inviter` shares no room with us. Our joined rooms are checked first, the server
+    /// is only asked when none of them has the inviter in it.
+    async fn is_stranger(&self, client: &Client, inviter: &UserId) -> bool {
+        if let Some(stranger) = self.strangers.lock().await.get(inviter) {
+            return *stranger;
+        }
+
+        let mut stranger = true;
+        for room in client.joined_rooms() {
+            if let Ok(Some(member)) = room.get_member_no_sync(inviter).await {
+                if *member.membership() == MembershipState::Join {
+                    stranger = false;
+                    break;
+                }
+            }
+        }
+        if stranger {
+            stranger = shared_rooms(client, inviter).await.is_empty();
+        }
+
+        self.strangers.lock().await.insert(inviter.to_owned(), stranger);
+        stranger
+    }
+
+    /// Whether the invite should be hidden because of [`AccountSettings::hide_invites_from_strangers`].
+    async fn is_hidden(&self, client: &Client, invite: &InviteInfo, settings: &AccountSettings) -> bool {
+        if !settings.hide_invites_from_strangers {
+            return false;
+        }
+        let Some(inviter) = invite.inviter.as_deref().and_then(|i| UserId::parse(i).ok()) else {
+            return false;
+        };
+        self.is_stranger(client, &inviter).await
+    }
+
+    /// Forget everything, used when the session ends.
+    pub async fn clear(&self) {
+        self.announced.lock().await.clear();
+        self.strangers.lock().await.clear();
+    }
+}
+
+/// Called by [`crate::events::client_events::ClientEvents`] for every stripped `m.room.member`
+/// event, emits `invite:received` when it's an invite for us that wasn't announced yet.
+///
+/// # Arguments
+/// * `event` - The stripped member event.
+/// * `room` - The room it belongs to.
+/// * `client` - The Matrix client that received it.
+/// * `app_handle` - The app handle used to emit the invite.
+pub async fn on_stripped_member_event(event: StrippedRoomMemberEvent, room: Room, client: Client, app_handle: AppHandle) {
+    if client.user_id() != Some(&*event.state_key) || event.content.membership != MembershipState::Invite {
+        return;
+    }
+    let inbox = &app_handle.state::<InviteState>().0;
+    if !inbox.announce(&client, room.room_id()).await {
+        return;
+    }
+
+    let invite = match invite_info(&room).await {
+        Ok(invite) => invite,
+        Err(e) => {
+            error!("Failed to read invite to {}: {}", room.room_id(), e);
+            return;
+        }
+    };
+    let Some(user_id) = client.user_id() else {
+        return;
+    };
+    let settings = AccountSettings::load(&app_handle, user_id.as_str()).unwrap_or_default();
+    if inbox.is_hidden(&client, &invite, &settings).await {
+        debug!("Hiding invite to {} from a stranger", room.room_id());
+        return;
+    }
+
+    if let Err(e) = app_handle.emit("invite:received", invite) {
+        error!("Failed to emit invite: {}", e);
+    }
+}
+
+/// Get the rooms we're invited to, newest first when the server tells us when they were sent.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client to list the invites of.
+/// * `invite_state` - The invite state, used to tell strangers apart.
+/// * `app_handle` - The app handle, used to look up the account settings.
+#[tauri::command]
+pub async fn get_invites(
+    state: State<'_, ClientState>,
+    invite_state: State<'_, InviteState>,
+    app_handle: AppHandle,
+) -> Result<Vec<InviteInfo>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let user_id = client.user_id().ok_or("Not logged in")?.to_string();
+    let settings = AccountSettings::load(&app_handle, &user_id).map_err(|e| e.to_string())?;
+
+    let inbox = &invite_state.0;
+    let mut invites = Vec::new();
+    for room in client.invited_rooms() {
+        match invite_info(&room).await {
+            Ok(invite) if !inbox.is_hidden(client, &invite, &settings).await => invites.push(invite),
+            Ok(_) => {}
+            Err(e) => error!("Failed to read invite to {}: {}", room.room_id(), e),
+        }
+    }
+    invites.sort_by(|a, b| b.ts.cmp(&a.ts));
+
+    Ok(invites)
+}
+
+fn invited_room(client: &Client, room_id: String) -> Result<Room, String> {
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    client
+        .invited_rooms()
+        .into_iter()
+        .find(|room| room.room_id() == room_id)
+        .ok_or_else(|| "Invite not found".to_string())
+}
+
+/// Accept an invite and join the room. DM invites are added to `m.direct` by the sdk.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room we were invited to.
+/// * `state` - The client state containing the Matrix client to join with.
+#[tauri::command]
+pub async fn accept_invite(
+    room_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let room = invited_room(client_handler.get_client(), room_id)?;
+    room.join().await.map_err(|e| format!("Failed to accept invite: {}", e))?;
+
+    Ok("invite accepted".into())
+}
+
+/// Decline an invite, optionally ignoring the user who sent it so they can't invite us again.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room we were invited to.
+/// * `ignore_inviter` - Whether to also add the inviter to the ignored users.
+/// * `state` - The client state containing the Matrix client to decline with.
+#[tauri::command]
+pub async fn decline_invite(
+    room_id: String,
+    ignore_inviter: bool,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room = invited_room(client, room_id)?;
+    let inviter = if ignore_inviter {
+        room.invite_details()
+            .await
+            .map_err(|e| e.to_string())?
+            .inviter
+            .map(|m| m.user_id().to_owned())
+    } else {
+        None
+    };
+
+    room.leave().await.map_err(|e| format!("Failed to decline invite: {}", e))?;
+
+    if let Some(inviter) = inviter {
+        client
+            .account()
+            .ignore_user(&inviter)
+            .await
+            .map_err(|e| format!("Invite declined, but failed to ignore {}: {}", inviter, e))?;
+        return Ok("invite declined and inviter ignored".into());
+    }
+    Ok("invite declined".into())
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use matrix_sdk::{Client, Room, RoomMemberships};
+use ruma::events::room::member::OriginalSyncRoomMemberEvent;
+use ruma::events::room::power_levels::RoomPowerLevels;
+use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Mutex;
+use tracing::{debug, error, trace};
+use crate::media::cache::MediaCache;
+use crate::rooms::power_levels::level_value;
+use crate::rooms::room_types::{
+    MembershipFilter, Role, RoomMemberChangePayload, RoomMemberInfo, RoomMembersPage,
+};
+use crate::{ClientState, MemberListState};
+
+/// Number of members returned per page when the caller doesn't say.
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// The sorted member lists of the rooms whose members were listed, so paging through a big room
+/// doesn't load and sort all of its members again for every page. A room's lists are dropped
+/// whenever its memberships or power levels change.
+pub struct MemberListCache {
+    lists: Mutex<HashMap<(OwnedRoomId, MembershipFilter), Arc<Vec<OwnedUserId>>>>,
+}
+
+impl MemberListCache {
+    pub fn new() -> Self {
+        MemberListCache {
+            lists: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the members of `room` matching `filter`, admins and moderators first, then by name.
+    async fn sorted_ids(
+        &self,
+        room: &Room,
+        filter: MembershipFilter,
+        power_levels: &RoomPowerLevels,
+    ) -> anyhow::Result<Arc<Vec<OwnedUserId>>> {
+        let key = (room.room_id().to_owned(), filter);
+        if let Some(ids) = self.lists.lock().await.get(&key) {
+            return Ok(ids.clone());
+        }
+
+        let mut members = room.members(RoomMemberships::from(filter)).await?;
+        members.sort_by_cached_key(|member| {
+            (
+                std::cmp::Reverse(power_levels.for_user(member.user_id())),
+                member.name().to_lowercase(),
+            )
+        });
+        let ids = Arc::new(members.iter().map(|member| member.user_id().to_owned()).collect::<Vec<_>>());
+
+        self.lists.lock().await.insert(key, ids.clone());
+        Ok(ids)
+    }
+
+    /// Forget the member lists of `room_id`.
+    pub async fn invalidate(&self, room_id: &RoomId) {
+        self.lists.lock().await.retain(|(cached, _), _| cached != room_id);
+    }
+
+    /// Forget every member list, used when the session ends.
+    pub async fn clear(&self) {
+        self.lists.lock().await.clear();
+    }
+}
+
+impl From<MembershipFilter> for RoomMemberships {
+    fn from(filter: MembershipFilter) -> Self {
+        match filter {
+            MembershipFilter::Active => RoomMemberships::ACTIVE,
+            MembershipFilter::Joined => RoomMemberships::JOIN,
+            MembershipFilter::Invited => RoomMemberships::INVITE,
+            MembershipFilter::Knocked => RoomMemberships::KNOCK,
+            MembershipFilter::Banned => RoomMemberships::BAN,
+            MembershipFilter::Left => RoomMemberships::LEAVE,
+            MembershipFilter::All => RoomMemberships::all(),
+        }
+    }
+}
+
+/// Called by [`crate::events::client_events::ClientEvents`] for every `m.room.member` event, emits
+/// a `room:member` event so open member lists can be patched, and drops the room's cached lists.
+///
+/// # Arguments
+/// * `event` - The member event.
+/// * `room` - The room it was sent in.
+/// * `app_handle` - The app handle used to emit the change.
+pub async fn on_membership_change(event: OriginalSyncRoomMemberEvent, room: Room, app_handle: AppHandle) {
+    trace!("Membership of {} in {} changed", event.state_key, room.room_id());
+    app_handle.state::<MemberListState>().0.invalidate(room.room_id()).await;
+
+    let payload = RoomMemberChangePayload {
+        room_id: room.room_id().to_string(),
+        user_id: event.state_key.to_string(),
+        membership: event.content.membership.to_string(),
+        display_name: event.content.displayname,
+        avatar_url: event.content.avatar_url.as_deref().map(MediaCache::avatar_url),
+    };
+    if let Err(e) = app_handle.emit("room:member", payload) {
+        error!("Failed to emit membership change: {}", e);
+    }
+}
+
+/// Called by [`crate::events::client_events::ClientEvents`] for every `m.room.power_levels` event,
+/// the member lists are sorted by power level so the room's cached lists are dropped.
+///
+/// # Arguments
+/// * `room` - The room whose power levels changed.
+/// * `app_handle` - The app handle used to reach the member list cache.
+pub async fn on_power_levels_change(room: Room, app_handle: AppHandle) {
+    trace!("Power levels of {} changed", room.room_id());
+    app_handle.state::<MemberListState>().0.invalidate(room.room_id()).await;
+}
+
+/// The presence we last received for `user_id`, from the local store.
+async fn cached_presence(client: &Client, user_id: &UserId) -> Option<String> {
+    let raw = client.state_store().get_presence_event(user_id).await.ok().flatten()?;
+    Some(raw.deserialize().ok()?.content.presence.to_string())
+}
+
+/// Get one page of a room's members, admins and moderators first, then by name.
+///
+/// The full member list is only fetched from the server the first time (the sync is lazy-loading
+/// members), after that pages come out of the store. The sorted list is cached until the room's
+/// memberships or power levels change, see [`MemberListCache`].
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to list the members of.
+/// * `membership_filter` - Which memberships to include, defaults to joined and invited members.
+/// * `offset` - How many members to skip, from the previous page's `next_offset`.
+/// * `limit` - The page size, defaults to 50.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+/// * `member_lists` - The state holding the cached member lists.
+#[tauri::command]
+pub async fn get_room_members(
+    room_id: String,
+    membership_filter: Option<MembershipFilter>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    state: State<'_, ClientState>,
+    member_lists: State<'_, MemberListState>,
+) -> Result<RoomMembersPage, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    let power_levels = room.power_levels().await.map_err(|e| e.to_string())?;
+    let users_default = power_levels.users_default;
+
+    let user_ids = member_lists
+        .0
+        .sorted_ids(&room, membership_filter.unwrap_or_default(), &power_levels)
+        .await
+        .map_err(|e| format!("Failed to load members: {}", e))?;
+
+    let total = user_ids.len();
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    let mut page = Vec::new();
+    for user_id in user_ids.iter().skip(offset).take(limit) {
+        let member = match room.get_member_no_sync(user_id).await {
+            Ok(Some(member)) => member,
+            Ok(None) => continue,
+            Err(e) => {
+                debug!("Failed to load member {} of {}: {}", user_id, room_id, e);
+                continue;
+            }
+        };
+        let level = power_levels.for_user(member.user_id());
+        page.push(RoomMemberInfo {
+            user_id: member.user_id().to_string(),
+            display_name: member.display_name().map(ToOwned::to_owned),
+            avatar_url: member.avatar_url().map(MediaCache::avatar_url),
+            power_level: level_value(level),
+            role: Role::from_level(level, users_default),
+            membership: member.membership().to_string(),
+            presence: cached_presence(client, member.user_id()).await,
+        });
+    }
+
+    let next_offset = offset + page.len();
+    Ok(RoomMembersPage {
+        members: page,
+        total,
+        next_offset: (next_offset < total).then_some(next_offset),
+    })
+}
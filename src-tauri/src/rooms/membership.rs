@@ -0,0 +1,260 @@
+use matrix_sdk::{Client, Room};
+use ruma::api::client::membership::invite_user::{self, v3::InvitationRecipient};
+use ruma::events::room::power_levels::{RoomPowerLevels, UserPowerLevel};
+use ruma::{Int, OwnedRoomId, OwnedUserId, UserId};
+use tauri::{AppHandle, Emitter, State};
+use tracing::{debug, error};
+use crate::rooms::direct::load_m_direct;
+use crate::rooms::room_types::{MembershipError, MembershipErrorKind, RoomLeftPayload};
+use crate::ClientState;
+
+impl MembershipError {
+    fn new(kind: MembershipErrorKind, message: impl Into<String>) -> Self {
+        MembershipError { kind, message: message.into() }
+    }
+
+    fn other(e: impl std::fmt::Display) -> Self {
+        Self::new(MembershipErrorKind::Other, e.to_string())
+    }
+}
+
+/// The moderation actions that need a minimum power level.
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Invite,
+    Kick,
+    Ban,
+    Unban,
+}
+
+/// Look up the room and target user, and check our power level allows `action` on them. Kicking,
+/// banning and unbanning additionally need a higher level than the target's.
+async fn prepare(
+    client: &Client,
+    room_id: String,
+    user_id: String,
+    action: Action,
+) -> Result<(Room, OwnedUserId), MembershipError> {
+    let room_id = OwnedRoomId::try_from(room_id)
+        .map_err(|e| MembershipError::new(MembershipErrorKind::NotFound, e.to_string()))?;
+    let user_id = OwnedUserId::try_from(user_id)
+        .map_err(|e| MembershipError::new(MembershipErrorKind::NotFound, e.to_string()))?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err(MembershipError::new(MembershipErrorKind::NotFound, "Room not found"));
+    };
+    let own_user_id = client
+        .user_id()
+        .ok_or_else(|| MembershipError::other("Not logged in"))?;
+
+    let power_levels = room.power_levels().await.map_err(MembershipError::other)?;
+    check_power_level(&power_levels, own_user_id, &user_id, action)?;
+
+    Ok((room, user_id))
+}
+
+fn check_power_level(
+    power_levels: &RoomPowerLevels,
+    own_user_id: &UserId,
+    target: &UserId,
+    action: Action,
+) -> Result<(), MembershipError> {
+    let own_level = power_levels.for_user(own_user_id);
+    let required: Int = match action {
+        Action::Invite => power_levels.invite,
+        Action::Kick => power_levels.kick,
+        Action::Ban | Action::Unban => power_levels.ban,
+    };
+
+    // room creators have an infinite level, which is always enough
+    if let UserPowerLevel::Int(own_level) = own_level {
+        if own_level < required {
+            return Err(MembershipError::new(
+                MembershipErrorKind::Forbidden,
+                format!("{:?} needs power level {}, you have {}", action, required, own_level),
+            ));
+        }
+    }
+    if !matches!(action, Action::Invite) && power_levels.for_user(target) >= own_level {
+        return Err(MembershipError::new(
+            MembershipErrorKind::Forbidden,
+            format!("{} has the same or a higher power level than you", target),
+        ));
+    }
+    Ok(())
+}
+
+/// Invite a user to a room. In encrypted rooms the keys to the room history are shared with them
+/// as well (MSC3061).
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to invite to.
+/// * `user_id` - The user to invite.
+/// * `reason` - An optional reason, shown to the invitee.
+/// * `state` - The client state containing the Matrix client to invite with.
+#[tauri::command]
+pub async fn invite_user(
+    room_id: String,
+    user_id: String,
+    reason: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, MembershipError> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let (room, user_id) = prepare(client, room_id, user_id, Action::Invite).await?;
+
+    // the sdk's invite doesn't take a reason, so send the request ourselves
+    let mut request = invite_user::v3::Request::new(
+        room.room_id().to_owned(),
+        InvitationRecipient::UserId { user_id: user_id.clone() },
+    );
+    request.reason = reason;
+    client.send(request).await.map_err(MembershipError::other)?;
+
+    if room.encryption_state().is_encrypted() {
+        if let Err(e) = room.share_history(&user_id).await {
+            error!("Failed to share room history with {}: {}", user_id, e);
+        }
+    }
+
+    Ok("user invited".into())
+}
+
+/// Remove a user from a room. They can join again if the room allows it.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to kick from.
+/// * `user_id` - The user to kick.
+/// * `reason` - An optional reason, shown to the user.
+/// * `state` - The client state containing the Matrix client to kick with.
+#[tauri::command]
+pub async fn kick_user(
+    room_id: String,
+    user_id: String,
+    reason: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, MembershipError> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let (room, user_id) = prepare(client_handler.get_client(), room_id, user_id, Action::Kick).await?;
+    room.kick_user(&user_id, reason.as_deref())
+        .await
+        .map_err(MembershipError::other)?;
+
+    Ok("user kicked".into())
+}
+
+/// Ban a user from a room, removing them if they're in it.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to ban from.
+/// * `user_id` - The user to ban.
+/// * `reason` - An optional reason, shown to the user.
+/// * `state` - The client state containing the Matrix client to ban with.
+#[tauri::command]
+pub async fn ban_user(
+    room_id: String,
+    user_id: String,
+    reason: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, MembershipError> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let (room, user_id) = prepare(client_handler.get_client(), room_id, user_id, Action::Ban).await?;
+    room.ban_user(&user_id, reason.as_deref())
+        .await
+        .map_err(MembershipError::other)?;
+
+    Ok("user banned".into())
+}
+
+/// Lift a ban, allowing the user to join or be invited again.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to unban from.
+/// * `user_id` - The user to unban.
+/// * `reason` - An optional reason.
+/// * `state` - The client state containing the Matrix client to unban with.
+#[tauri::command]
+pub async fn unban_user(
+    room_id: String,
+    user_id: String,
+    reason: Option<String>,
+    state: State<'_, ClientState>,
+) -> Result<String, MembershipError> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let (room, user_id) = prepare(client_handler.get_client(), room_id, user_id, Action::Unban).await?;
+    room.unban_user(&user_id, reason.as_deref())
+        .await
+        .map_err(MembershipError::other)?;
+
+    Ok("user unbanned".into())
+}
+
+/// Drop `room_id` from every entry of `m.direct`, so a left DM stops showing up as one.
+async fn remove_from_m_direct(client: &Client, room_id: &OwnedRoomId) -> anyhow::Result<()> {
+    let mut direct = load_m_direct(client).await?;
+
+    let before: usize = direct.values().map(Vec::len).sum();
+    for rooms in direct.values_mut() {
+        rooms.retain(|r| r != room_id);
+    }
+    direct.retain(|_, rooms| !rooms.is_empty());
+    if direct.values().map(Vec::len).sum::<usize>() != before {
+        client.account().set_account_data(direct).await?;
+    }
+    Ok(())
+}
+
+/// Leave a room, optionally forgetting it so it disappears from the room list and its history is
+/// dropped from the store. Either way it's removed from `m.direct` and a `room:left` event is
+/// emitted so the space trees and DM list drop it.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to leave.
+/// * `forget` - Whether to also forget the room.
+/// * `state` - The client state containing the Matrix client to leave with.
+/// * `app_handle` - The app handle used to emit the `room:left` event.
+#[tauri::command]
+pub async fn leave_room(
+    room_id: String,
+    forget: bool,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, MembershipError> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id)
+        .map_err(|e| MembershipError::new(MembershipErrorKind::NotFound, e.to_string()))?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err(MembershipError::new(MembershipErrorKind::NotFound, "Room not found"));
+    };
+
+    debug!("Leaving room {} (forget: {})", room_id, forget);
+    room.leave().await.map_err(MembershipError::other)?;
+
+    // we're out of the room at this point, so clean up even if forgetting it fails below
+    if let Err(e) = remove_from_m_direct(client, &room_id).await {
+        error!("Failed to remove {} from m.direct: {}", room_id, e);
+    }
+
+    let forget_result = if forget { room.forget().await } else { Ok(()) };
+
+    let payload = RoomLeftPayload {
+        room_id: room_id.to_string(),
+        forgotten: forget && forget_result.is_ok(),
+    };
+    if let Err(e) = app_handle.emit("room:left", payload) {
+        error!("Failed to emit room left event: {}", e);
+    }
+
+    forget_result.map_err(|e| MembershipError::other(format!("Left the room, but failed to forget it: {}", e)))?;
+    Ok(if forget { "room left and forgotten" } else { "room left" }.into())
+}
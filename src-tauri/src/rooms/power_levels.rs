@@ -0,0 +1,239 @@
+use matrix_sdk::{Client, Room, RoomMemberships};
+use ruma::events::room::power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent, UserPowerLevel};
+use ruma::events::{StateEventType, TimelineEventType};
+use ruma::{Int, OwnedRoomId, OwnedUserId};
+use tauri::State;
+use tracing::debug;
+use crate::rooms::room_types::{MemberPowerLevel, PowerLevelsView, Role};
+use crate::ClientState;
+
+/// Power level of the admin role.
+const ADMIN_LEVEL: i64 = 100;
+/// Power level of the moderator role.
+const MODERATOR_LEVEL: i64 = 50;
+
+impl Role {
+    /// The named role a power level falls into.
+    ///
+    /// # Arguments
+    /// * `level` - The power level.
+    /// * `users_default` - The room's default user level.
+    pub fn from_level(level: UserPowerLevel, users_default: Int) -> Self {
+        let level = match level {
+            UserPowerLevel::Infinite => return Role::Creator,
+            UserPowerLevel::Int(level) => level,
+        };
+        if i64::from(level) >= ADMIN_LEVEL {
+            Role::Admin
+        } else if i64::from(level) >= MODERATOR_LEVEL {
+            Role::Moderator
+        } else if level == users_default {
+            Role::User
+        } else {
+            Role::Custom
+        }
+    }
+}
+
+/// A power level as sent to the frontend, `None` for the infinite level of room creators.
+pub(crate) fn level_value(level: UserPowerLevel) -> Option<i64> {
+    match level {
+        UserPowerLevel::Infinite => None,
+        UserPowerLevel::Int(level) => Some(level.into()),
+    }
+}
+
+/// Whether `level` is enough to be an admin, which room creators always are.
+fn is_admin(level: UserPowerLevel) -> bool {
+    match level {
+        UserPowerLevel::Infinite => true,
+        UserPowerLevel::Int(level) => i64::from(level) >= ADMIN_LEVEL,
+    }
+}
+
+/// Look up the room, check we may change its power levels and return them.
+async fn editable_power_levels(client: &Client, room_id: String) -> Result<(Room, RoomPowerLevels), String> {
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let power_levels = room.power_levels().await.map_err(|e| e.to_string())?;
+
+    if !power_levels.user_can_send_state(user_id, StateEventType::RoomPowerLevels) {
+        return Err("You don't have permission to change power levels in this room".to_string());
+    }
+    Ok((room, power_levels))
+}
+
+fn to_int(level: i64) -> Result<Int, String> {
+    Int::new(level).ok_or_else(|| format!("{} is not a valid power level", level))
+}
+
+/// Get the room's power levels, with every user's named role.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to read the power levels of.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn get_power_levels(
+    room_id: String,
+    state: State<'_, ClientState>,
+) -> Result<PowerLevelsView, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let power_levels = room.power_levels().await.map_err(|e| e.to_string())?;
+
+    let users_default = power_levels.users_default;
+    let mut users: Vec<MemberPowerLevel> = power_levels
+        .users
+        .iter()
+        .map(|(user_id, level)| MemberPowerLevel {
+            user_id: user_id.to_string(),
+            level: Some(i64::from(*level)),
+            role: Role::from_level(UserPowerLevel::Int(*level), users_default),
+        })
+        .collect();
+    users.sort_by(|a, b| b.level.cmp(&a.level).then_with(|| a.user_id.cmp(&b.user_id)));
+
+    Ok(PowerLevelsView {
+        users,
+        events: power_levels
+            .events
+            .iter()
+            .map(|(event_type, level)| (event_type.to_string(), i64::from(*level)))
+            .collect(),
+        users_default: users_default.into(),
+        events_default: power_levels.events_default.into(),
+        state_default: power_levels.state_default.into(),
+        ban: power_levels.ban.into(),
+        kick: power_levels.kick.into(),
+        invite: power_levels.invite.into(),
+        redact: power_levels.redact.into(),
+        own_level: level_value(power_levels.for_user(user_id)),
+        can_edit: power_levels.user_can_send_state(user_id, StateEventType::RoomPowerLevels),
+    })
+}
+
+/// Promote or demote a user. We can't raise anyone above our own level or change someone at or
+/// above it, and the last admin of a room can't demote themselves, since nobody could ever
+/// promote a new one. The level of room creators can't be changed at all.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to change the level in.
+/// * `user_id` - The user to promote or demote.
+/// * `level` - The new power level, e.g. 100 for admin or 50 for moderator.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn set_user_power_level(
+    room_id: String,
+    user_id: String,
+    level: i64,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let (room, power_levels) = editable_power_levels(client, room_id).await?;
+    let own_user_id = client.user_id().ok_or("Not logged in")?;
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    let level = to_int(level)?;
+
+    let own_level = power_levels.for_user(own_user_id);
+    let current_level = power_levels.for_user(&user_id);
+    if current_level == UserPowerLevel::Infinite {
+        return Err("Room creators always have the highest power level".to_string());
+    }
+    if UserPowerLevel::Int(level) > own_level {
+        return Err("You can't give someone a higher power level than your own".to_string());
+    }
+
+    if user_id == own_user_id {
+        // creators aren't listed in `users`, so look at who is actually in the room
+        let members = room
+            .members(RoomMemberships::JOIN)
+            .await
+            .map_err(|e| format!("Failed to load members: {}", e))?;
+        let is_last_admin = is_admin(own_level)
+            && !members
+                .iter()
+                .any(|member| member.user_id() != own_user_id && is_admin(power_levels.for_user(member.user_id())));
+        if is_last_admin && UserPowerLevel::Int(level) < own_level {
+            return Err(
+                "You're the last admin of this room, promote someone else before demoting yourself".to_string(),
+            );
+        }
+    } else if current_level >= own_level {
+        return Err("You can't change the power level of someone at or above your own".to_string());
+    }
+
+    let mut content = RoomPowerLevelsEventContent::from(power_levels);
+    if level == content.users_default {
+        content.users.remove(&user_id);
+    } else {
+        content.users.insert(user_id.clone(), level);
+    }
+
+    debug!("Setting power level of {} to {} in {}", user_id, level, room.room_id());
+    room.send_state_event(content)
+        .await
+        .map_err(|e| format!("Failed to change power level: {}", e))?;
+
+    Ok("power level changed".into())
+}
+
+/// Change the level required to send a specific event type, e.g. `m.room.name`.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to change the level in.
+/// * `event_type` - The event type to change the required level of.
+/// * `level` - The new required level, or `None` to fall back to the room defaults.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn set_event_power_level(
+    room_id: String,
+    event_type: String,
+    level: Option<i64>,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let (room, power_levels) = editable_power_levels(client, room_id).await?;
+    let own_user_id = client.user_id().ok_or("Not logged in")?;
+    let own_level = power_levels.for_user(own_user_id);
+    let event_type = TimelineEventType::from(event_type.as_str());
+
+    let mut content = RoomPowerLevelsEventContent::from(power_levels);
+    // the spec doesn't let us touch a level above our own, or raise one above it
+    if content.events.get(&event_type).is_some_and(|current| UserPowerLevel::Int(*current) > own_level) {
+        return Err("You can't change a level that is higher than your own".to_string());
+    }
+    match level.map(to_int).transpose()? {
+        Some(level) if UserPowerLevel::Int(level) > own_level => {
+            return Err("You can't require a higher level than your own".to_string());
+        }
+        Some(level) => {
+            content.events.insert(event_type.clone(), level);
+        }
+        None => {
+            content.events.remove(&event_type);
+        }
+    }
+
+    debug!("Setting power level of {} events in {}", event_type, room.room_id());
+    room.send_state_event(content)
+        .await
+        .map_err(|e| format!("Failed to change event power level: {}", e))?;
+
+    Ok("event power level changed".into())
+}
@@ -0,0 +1,94 @@
+use matrix_sdk::room::Receipts;
+use ruma::events::receipt::{ReceiptThread, ReceiptType};
+use ruma::{OwnedEventId, OwnedRoomId};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+use tracing::debug;
+use crate::settings::account_settings::AccountSettings;
+use crate::ClientState;
+
+/// A single member's read position, as shown under a message in the timeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadReceipt {
+    pub user_id: String,
+    pub ts: Option<u64>,
+}
+
+/// Mark a room as read up to the given event. This sends a read receipt (public or private,
+/// depending on [`AccountSettings::send_public_receipts`]) along with the `m.fully_read` marker,
+/// so other clients stop showing the room as unread.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room the user is viewing.
+/// * `event_id` - The ID of the latest event the user has seen in that room.
+/// * `state` - The client state containing the Matrix client to send the receipts with.
+/// * `app_handle` - The app handle, used to look up the account settings.
+#[tauri::command]
+pub async fn mark_room_read(
+    room_id: String,
+    event_id: String,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let event_id = OwnedEventId::try_from(event_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    let user_id = client.user_id().ok_or("Not logged in")?.to_string();
+    let settings = AccountSettings::load(&app_handle, &user_id).map_err(|e| e.to_string())?;
+
+    let receipts = Receipts::new().fully_read_marker(event_id.clone());
+    let receipts = if settings.send_public_receipts {
+        receipts.public_read_receipt(event_id)
+    } else {
+        receipts.private_read_receipt(event_id)
+    };
+
+    debug!("Marking room {} as read (public: {})", room_id, settings.send_public_receipts);
+    room.send_multiple_receipts(receipts).await.map_err(|e| e.to_string())?;
+
+    Ok("room marked as read".into())
+}
+
+/// Get the members who have read up to the given event, according to the receipts we have stored.
+/// Live updates for the same information are emitted as `matrix:receipt` events.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room the event is in.
+/// * `event_id` - The ID of the event to get the receipts for.
+/// * `state` - The client state containing the Matrix client to read the receipts from.
+#[tauri::command]
+pub async fn get_event_receipts(
+    room_id: String,
+    event_id: String,
+    state: State<'_, ClientState>,
+) -> Result<Vec<ReadReceipt>, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let event_id = OwnedEventId::try_from(event_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    let receipts = room
+        .load_event_receipts(ReceiptType::Read, ReceiptThread::Unthreaded, &event_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(receipts
+        .into_iter()
+        .map(|(user_id, receipt)| ReadReceipt {
+            user_id: user_id.to_string(),
+            ts: receipt.ts.map(|ts| ts.get().into()),
+        })
+        .collect())
+}
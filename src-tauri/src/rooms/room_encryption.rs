@@ -0,0 +1,134 @@
+use matrix_sdk::Room;
+use ruma::events::room::history_visibility::HistoryVisibility;
+use ruma::events::StateEventType;
+use ruma::{OwnedRoomId, OwnedUserId};
+use tauri::State;
+use tracing::debug;
+use crate::rooms::room_types::RoomEncryptionInfo;
+use crate::ClientState;
+
+/// The `m.room.encryption` algorithm of a room, or `None` if it isn't encrypted (or we don't know
+/// yet).
+///
+/// # Arguments
+/// * `room` - The room to look at.
+pub fn encryption_algorithm(room: &Room) -> Option<String> {
+    room.encryption_settings().map(|settings| settings.algorithm.to_string())
+}
+
+/// Get the encryption state of a room, along with whether its history is shared with invited
+/// members.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to inspect.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn get_room_encryption(
+    room_id: String,
+    state: State<'_, ClientState>,
+) -> Result<RoomEncryptionInfo, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client_handler.get_client().get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    let settings = room.encryption_settings();
+    let history_visibility = room.history_visibility_or_default();
+    let is_encrypted = room.encryption_state().is_encrypted();
+    // the sdk only marks keys as shareable when new members are allowed to see the history
+    let history_is_shared = matches!(
+        history_visibility,
+        HistoryVisibility::Shared | HistoryVisibility::WorldReadable
+    );
+
+    Ok(RoomEncryptionInfo {
+        room_id: room_id.to_string(),
+        is_encrypted,
+        encryption_algorithm: settings.as_ref().map(|s| s.algorithm.to_string()),
+        rotation_period_ms: settings.as_ref().and_then(|s| s.rotation_period_ms).map(u64::from),
+        rotation_period_msgs: settings.as_ref().and_then(|s| s.rotation_period_msgs).map(u64::from),
+        history_visibility: history_visibility.to_string(),
+        shares_history_on_invite: is_encrypted && history_is_shared,
+    })
+}
+
+/// Turn on end-to-end encryption in an existing room. This can never be undone, so the frontend
+/// has to warn the user and pass `confirm_irreversible` once they've agreed.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to encrypt.
+/// * `confirm_irreversible` - Whether the user acknowledged that encryption can't be turned off.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn enable_room_encryption(
+    room_id: String,
+    confirm_irreversible: bool,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    if room.encryption_state().is_encrypted() {
+        return Err("Room is already encrypted".to_string());
+    }
+    if !confirm_irreversible {
+        return Err(
+            "Encryption can't be turned off again once it is enabled, confirm to continue".to_string(),
+        );
+    }
+
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let power_levels = room.power_levels().await.map_err(|e| e.to_string())?;
+    if !power_levels.user_can_send_state(user_id, StateEventType::RoomEncryption) {
+        return Err("You don't have permission to enable encryption in this room".to_string());
+    }
+
+    debug!("Enabling encryption in room {}", room_id);
+    room.enable_encryption()
+        .await
+        .map_err(|e| format!("Failed to enable encryption, the room is unchanged: {}", e))?;
+
+    Ok("room encryption enabled".into())
+}
+
+/// Share the keys to the room's history with a member who was already invited (MSC3061). New
+/// invites sent through the app do this automatically.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room whose history should be shared.
+/// * `user_id` - The invited user to share the keys with.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn share_room_history(
+    room_id: String,
+    user_id: String,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let user_id = OwnedUserId::try_from(user_id).map_err(|e| e.to_string())?;
+    let Some(room) = client_handler.get_client().get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+
+    if !room.encryption_state().is_encrypted() {
+        return Err("Room isn't encrypted, there are no keys to share".to_string());
+    }
+
+    room.share_history(&user_id)
+        .await
+        .map_err(|e| format!("Failed to share room history: {}", e))?;
+
+    Ok("room history shared".into())
+}
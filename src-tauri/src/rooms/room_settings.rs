@@ -0,0 +1,300 @@
+use std::path::PathBuf;
+use matrix_sdk::Room;
+use ruma::events::room::avatar::RoomAvatarEventContent;
+use ruma::events::room::canonical_alias::RoomCanonicalAliasEventContent;
+use ruma::events::room::guest_access::{GuestAccess, RoomGuestAccessEventContent};
+use ruma::events::room::history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent};
+use ruma::events::room::join_rules::{AllowRule, JoinRule, Restricted, RoomJoinRulesEventContent};
+use ruma::events::room::name::RoomNameEventContent;
+use ruma::events::room::power_levels::RoomPowerLevels;
+use ruma::events::room::topic::RoomTopicEventContent;
+use ruma::events::StateEventType;
+use ruma::{OwnedRoomAliasId, OwnedRoomId, UserId};
+use tauri::State;
+use tracing::debug;
+use crate::media::cache::MediaCache;
+use crate::media::upload::UploadManager;
+use crate::rooms::room_types::{Editable, JoinRuleSetting, RoomSettings, RoomSettingsUpdate};
+use crate::ClientState;
+
+impl From<&JoinRule> for JoinRuleSetting {
+    fn from(rule: &JoinRule) -> Self {
+        let allowed_spaces = |restricted: &Restricted| {
+            restricted
+                .allow
+                .iter()
+                .filter_map(|allow| match allow {
+                    AllowRule::RoomMembership(membership) => Some(membership.room_id.to_string()),
+                    _ => None,
+                })
+                .collect()
+        };
+        match rule {
+            JoinRule::Invite => JoinRuleSetting::Invite,
+            JoinRule::Public => JoinRuleSetting::Public,
+            JoinRule::Knock => JoinRuleSetting::Knock,
+            JoinRule::Restricted(restricted) => JoinRuleSetting::Restricted {
+                allowed_spaces: allowed_spaces(restricted),
+            },
+            JoinRule::KnockRestricted(restricted) => JoinRuleSetting::KnockRestricted {
+                allowed_spaces: allowed_spaces(restricted),
+            },
+            _ => JoinRuleSetting::Other,
+        }
+    }
+}
+
+impl TryFrom<JoinRuleSetting> for JoinRule {
+    type Error = String;
+
+    fn try_from(setting: JoinRuleSetting) -> Result<Self, Self::Error> {
+        let restricted = |spaces: Vec<String>| -> Result<Restricted, String> {
+            let allow = spaces
+                .into_iter()
+                .map(|space| OwnedRoomId::try_from(space).map(AllowRule::room_membership))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            Ok(Restricted::new(allow))
+        };
+        Ok(match setting {
+            JoinRuleSetting::Invite => JoinRule::Invite,
+            JoinRuleSetting::Public => JoinRule::Public,
+            JoinRuleSetting::Knock => JoinRule::Knock,
+            JoinRuleSetting::Restricted { allowed_spaces } => JoinRule::Restricted(restricted(allowed_spaces)?),
+            JoinRuleSetting::KnockRestricted { allowed_spaces } => {
+                JoinRule::KnockRestricted(restricted(allowed_spaces)?)
+            }
+            JoinRuleSetting::Other => return Err("Unknown join rule".to_string()),
+        })
+    }
+}
+
+/// Read the settings shown in the room settings editor, each with whether we can change it.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to read the settings of.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn get_room_settings(
+    room_id: String,
+    state: State<'_, ClientState>,
+) -> Result<RoomSettings, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let power_levels = room.power_levels().await.map_err(|e| e.to_string())?;
+    let can_edit = |event_type: StateEventType| power_levels.user_can_send_state(user_id, event_type);
+
+    let join_rule = room
+        .join_rule()
+        .map(|rule| JoinRuleSetting::from(&rule))
+        .unwrap_or(JoinRuleSetting::Invite);
+
+    Ok(RoomSettings {
+        room_id: room_id.to_string(),
+        name: Editable { value: room.name(), can_edit: can_edit(StateEventType::RoomName) },
+        topic: Editable { value: room.topic(), can_edit: can_edit(StateEventType::RoomTopic) },
+        avatar_url: Editable {
+            value: room.avatar_url().map(|m| MediaCache::avatar_url(&m)),
+            can_edit: can_edit(StateEventType::RoomAvatar),
+        },
+        canonical_alias: Editable {
+            value: room.canonical_alias().map(|alias| alias.to_string()),
+            can_edit: can_edit(StateEventType::RoomCanonicalAlias),
+        },
+        alt_aliases: Editable {
+            value: room.alt_aliases().iter().map(|alias| alias.to_string()).collect(),
+            can_edit: can_edit(StateEventType::RoomCanonicalAlias),
+        },
+        join_rule: Editable { value: join_rule, can_edit: can_edit(StateEventType::RoomJoinRules) },
+        history_visibility: Editable {
+            value: room.history_visibility_or_default().to_string(),
+            can_edit: can_edit(StateEventType::RoomHistoryVisibility),
+        },
+        guest_access: Editable {
+            value: room.guest_access() == GuestAccess::CanJoin,
+            can_edit: can_edit(StateEventType::RoomGuestAccess),
+        },
+    })
+}
+
+fn require(
+    power_levels: &RoomPowerLevels,
+    user_id: &UserId,
+    event_type: StateEventType,
+    what: &str,
+) -> Result<(), String> {
+    if power_levels.user_can_send_state(user_id, event_type) {
+        Ok(())
+    } else {
+        Err(format!("You don't have permission to change the {} of this room", what))
+    }
+}
+
+/// A [`RoomSettingsUpdate`] with every field parsed, so nothing is sent before all of them are
+/// known to be valid.
+struct ParsedUpdate {
+    name: Option<String>,
+    topic: Option<String>,
+    avatar_path: Option<PathBuf>,
+    remove_avatar: bool,
+    aliases: Option<RoomCanonicalAliasEventContent>,
+    join_rule: Option<JoinRule>,
+    history_visibility: Option<HistoryVisibility>,
+    guest_access: Option<GuestAccess>,
+}
+
+fn parse_history_visibility(value: &str) -> Result<HistoryVisibility, String> {
+    match value {
+        "invited" => Ok(HistoryVisibility::Invited),
+        "joined" => Ok(HistoryVisibility::Joined),
+        "shared" => Ok(HistoryVisibility::Shared),
+        "world_readable" => Ok(HistoryVisibility::WorldReadable),
+        _ => Err(format!("Unknown history visibility: {}", value)),
+    }
+}
+
+fn parse_update(room: &Room, update: RoomSettingsUpdate) -> Result<ParsedUpdate, String> {
+    let avatar_path = match update.avatar_path {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            let mime = mime_guess::from_path(&path).first_or_octet_stream();
+            if mime.type_() != mime_guess::mime::IMAGE {
+                return Err("Room avatars have to be images".to_string());
+            }
+            Some(path)
+        }
+        None => None,
+    };
+
+    let aliases = if update.canonical_alias.is_some() || update.alt_aliases.is_some() {
+        // both live in the same event, keep whichever one isn't being changed
+        let mut content = RoomCanonicalAliasEventContent::new();
+        content.alias = match update.canonical_alias {
+            Some(alias) if alias.is_empty() => None,
+            Some(alias) => Some(OwnedRoomAliasId::try_from(alias).map_err(|e| e.to_string())?),
+            None => room.canonical_alias(),
+        };
+        content.alt_aliases = match update.alt_aliases {
+            Some(aliases) => aliases
+                .into_iter()
+                .map(OwnedRoomAliasId::try_from)
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())?,
+            None => room.alt_aliases(),
+        };
+        Some(content)
+    } else {
+        None
+    };
+
+    Ok(ParsedUpdate {
+        name: update.name,
+        topic: update.topic,
+        avatar_path,
+        remove_avatar: update.remove_avatar,
+        aliases,
+        join_rule: update.join_rule.map(JoinRule::try_from).transpose()?,
+        history_visibility: update.history_visibility.as_deref().map(parse_history_visibility).transpose()?,
+        guest_access: update
+            .guest_access
+            .map(|guest_access| if guest_access { GuestAccess::CanJoin } else { GuestAccess::Forbidden }),
+    })
+}
+
+/// Apply the given changes to a room's state. Permissions are checked and every field is parsed
+/// before anything is sent, so a rejected field doesn't leave the room half updated.
+///
+/// # Arguments
+/// * `room_id` - The ID of the room to change.
+/// * `update` - The changes, fields left out stay as they are.
+/// * `state` - The client state containing the Matrix client the room belongs to.
+#[tauri::command]
+pub async fn update_room_settings(
+    room_id: String,
+    update: RoomSettingsUpdate,
+    state: State<'_, ClientState>,
+) -> Result<String, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let client = client_handler.get_client();
+
+    let room_id = OwnedRoomId::try_from(room_id).map_err(|e| e.to_string())?;
+    let Some(room) = client.get_room(&room_id) else {
+        return Err("Room not found".to_string());
+    };
+    let user_id = client.user_id().ok_or("Not logged in")?;
+    let power_levels = room.power_levels().await.map_err(|e| e.to_string())?;
+
+    if update.name.is_some() {
+        require(&power_levels, user_id, StateEventType::RoomName, "name")?;
+    }
+    if update.topic.is_some() {
+        require(&power_levels, user_id, StateEventType::RoomTopic, "topic")?;
+    }
+    if update.avatar_path.is_some() || update.remove_avatar {
+        require(&power_levels, user_id, StateEventType::RoomAvatar, "avatar")?;
+    }
+    if update.canonical_alias.is_some() || update.alt_aliases.is_some() {
+        require(&power_levels, user_id, StateEventType::RoomCanonicalAlias, "addresses")?;
+    }
+    if update.join_rule.is_some() {
+        require(&power_levels, user_id, StateEventType::RoomJoinRules, "join rule")?;
+    }
+    if update.history_visibility.is_some() {
+        require(&power_levels, user_id, StateEventType::RoomHistoryVisibility, "history visibility")?;
+    }
+    if update.guest_access.is_some() {
+        require(&power_levels, user_id, StateEventType::RoomGuestAccess, "guest access")?;
+    }
+
+    let update = parse_update(&room, update)?;
+    apply_update(&room, update).await.map_err(|e| format!("Failed to update room settings: {}", e))?;
+    Ok("room settings updated".into())
+}
+
+async fn apply_update(room: &Room, update: ParsedUpdate) -> anyhow::Result<()> {
+    debug!("Updating settings of room {}", room.room_id());
+
+    // upload first, a failed upload shouldn't leave the other fields changed
+    let avatar = match update.avatar_path {
+        Some(path) => Some(UploadManager::upload_media(&room.client(), path).await?.0),
+        None => None,
+    };
+
+    if let Some(name) = update.name {
+        room.send_state_event(RoomNameEventContent::new(name)).await?;
+    }
+    if let Some(topic) = update.topic {
+        room.send_state_event(RoomTopicEventContent::new(topic)).await?;
+    }
+
+    if let Some(mxc) = avatar {
+        let mut content = RoomAvatarEventContent::new();
+        content.url = Some(mxc);
+        room.send_state_event(content).await?;
+    } else if update.remove_avatar {
+        room.send_state_event(RoomAvatarEventContent::new()).await?;
+    }
+
+    if let Some(content) = update.aliases {
+        room.send_state_event(content).await?;
+    }
+    if let Some(join_rule) = update.join_rule {
+        room.send_state_event(RoomJoinRulesEventContent::new(join_rule)).await?;
+    }
+    if let Some(history_visibility) = update.history_visibility {
+        room.send_state_event(RoomHistoryVisibilityEventContent::new(history_visibility)).await?;
+    }
+    if let Some(guest_access) = update.guest_access {
+        room.send_state_event(RoomGuestAccessEventContent::new(guest_access)).await?;
+    }
+
+    Ok(())
+}
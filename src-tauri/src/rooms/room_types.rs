@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -5,8 +6,12 @@ pub struct RawRoom {
     pub id: String,
     pub name: Option<String>,
     pub topic: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
     pub avatar_url: Option<String>,
-    pub is_space: bool
+    pub is_space: bool,
+    pub is_encrypted: bool,
+    /// The `m.room.encryption` algorithm, e.g. `m.megolm.v1.aes-sha2`
+    pub encryption_algorithm: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,4 +26,230 @@ pub struct DmRoom {
     #[serde(flatten)]
     pub base: RawRoom,
     pub members: Vec<String>
-}
\ No newline at end of file
+}
+
+/// The encryption state of a room, returned by `get_room_encryption`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomEncryptionInfo {
+    pub room_id: String,
+    pub is_encrypted: bool,
+    pub encryption_algorithm: Option<String>,
+    /// How long a megolm session is used before it's rotated, in milliseconds.
+    pub rotation_period_ms: Option<u64>,
+    /// How many messages a megolm session is used for before it's rotated.
+    pub rotation_period_msgs: Option<u64>,
+    /// The room's `m.room.history_visibility`, e.g. `shared` or `joined`.
+    pub history_visibility: String,
+    /// Whether the keys to the room's history are shared with users when they get invited
+    /// (MSC3061), which needs the history to be visible to new members in the first place.
+    pub shares_history_on_invite: bool,
+}
+
+/// What `repair_m_direct` changed in the `m.direct` account data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectRepairReport {
+    /// DM rooms that are still there and stayed in `m.direct`.
+    pub kept: usize,
+    /// Entries for rooms we left or never joined, which were removed.
+    pub removed: usize,
+    /// Joined rooms created as DMs that were missing from `m.direct` and got added back.
+    pub added: usize,
+}
+
+/// A room we were invited to, as listed in the invites inbox and emitted as `invite:received`
+/// events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteInfo {
+    #[serde(flatten)]
+    pub base: RawRoom,
+    pub inviter: Option<String>,
+    pub inviter_display_name: Option<String>,
+    /// Whether the inviter marked this as a direct chat.
+    pub is_direct: bool,
+    pub member_count: u64,
+    /// When the invite was sent, in milliseconds since the unix epoch. Invites only come with
+    /// stripped state, so servers don't always tell us.
+    pub ts: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipErrorKind {
+    /// Our power level in the room isn't high enough, checked against `m.room.power_levels` before
+    /// asking the server.
+    Forbidden,
+    /// The room or user doesn't exist, or the ID is malformed.
+    NotFound,
+    Other,
+}
+
+/// Error returned by the membership commands, typed so the frontend can grey out actions the user
+/// isn't allowed to take instead of showing the raw server error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MembershipError {
+    pub kind: MembershipErrorKind,
+    pub message: String,
+}
+
+/// Emitted as `room:left` after we leave (and maybe forget) a room, so it can be dropped from the
+/// room list, space trees and DMs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomLeftPayload {
+    pub room_id: String,
+    pub forgotten: bool,
+}
+
+/// A room setting along with whether our power level allows changing it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Editable<T> {
+    pub value: T,
+    pub can_edit: bool,
+}
+
+/// Who can join a room, mirroring `m.room.join_rules`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum JoinRuleSetting {
+    Invite,
+    Public,
+    Knock,
+    /// Members of any of the given spaces can join without an invite.
+    Restricted { allowed_spaces: Vec<String> },
+    /// Like `Restricted`, but everyone else can still knock.
+    KnockRestricted { allowed_spaces: Vec<String> },
+    /// A rule Echelon doesn't know, only shown and never written.
+    Other,
+}
+
+/// The core state of a room, as shown in the room settings editor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSettings {
+    pub room_id: String,
+    pub name: Editable<Option<String>>,
+    pub topic: Editable<Option<String>>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Editable<Option<String>>,
+    pub canonical_alias: Editable<Option<String>>,
+    /// Edited together with the canonical alias, they live in the same state event.
+    pub alt_aliases: Editable<Vec<String>>,
+    pub join_rule: Editable<JoinRuleSetting>,
+    /// One of `invited`, `joined`, `shared` or `world_readable`.
+    pub history_visibility: Editable<String>,
+    /// Whether guests are allowed to join.
+    pub guest_access: Editable<bool>,
+}
+
+/// Changes to apply with `update_room_settings`, fields left out stay as they are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoomSettingsUpdate {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    /// Path of an image to upload as the new room avatar.
+    pub avatar_path: Option<String>,
+    pub remove_avatar: bool,
+    /// The new canonical alias, an empty string removes it.
+    pub canonical_alias: Option<String>,
+    pub alt_aliases: Option<Vec<String>>,
+    pub join_rule: Option<JoinRuleSetting>,
+    /// One of `invited`, `joined`, `shared` or `world_readable`.
+    pub history_visibility: Option<String>,
+    pub guest_access: Option<bool>,
+}
+
+/// The named roles of the room power levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Power level 100 and above.
+    Admin,
+    /// Power level 50 up to 99.
+    Moderator,
+    /// The room's default user level.
+    User,
+    /// Anything else.
+    Custom,
+    /// A creator of a room of version 12 or later, whose power level is infinite.
+    Creator,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberPowerLevel {
+    pub user_id: String,
+    /// `None` for room creators, whose level is infinite.
+    pub level: Option<i64>,
+    pub role: Role,
+}
+
+/// Structured view of a room's `m.room.power_levels`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerLevelsView {
+    /// Users with an explicit level, highest first.
+    pub users: Vec<MemberPowerLevel>,
+    /// Levels required to send specific event types, keyed by event type.
+    pub events: HashMap<String, i64>,
+    pub users_default: i64,
+    pub events_default: i64,
+    pub state_default: i64,
+    pub ban: i64,
+    pub kick: i64,
+    pub invite: i64,
+    pub redact: i64,
+    /// `None` if we created the room, since creators' level is infinite.
+    pub own_level: Option<i64>,
+    /// Whether we're allowed to change the power levels at all.
+    pub can_edit: bool,
+}
+
+/// Which members `get_room_members` returns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipFilter {
+    /// Joined and invited members, as shown in the member list.
+    #[default]
+    Active,
+    Joined,
+    Invited,
+    Knocked,
+    Banned,
+    Left,
+    All,
+}
+
+/// A room member, as shown in the member list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomMemberInfo {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Option<String>,
+    /// `None` for room creators, whose level is infinite.
+    pub power_level: Option<i64>,
+    pub role: Role,
+    /// `join`, `invite`, `knock`, `ban` or `leave`.
+    pub membership: String,
+    /// `online`, `unavailable` or `offline`, if the server shares presence with us.
+    pub presence: Option<String>,
+}
+
+/// One page of `get_room_members`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomMembersPage {
+    pub members: Vec<RoomMemberInfo>,
+    /// Number of members matching the filter, across all pages.
+    pub total: usize,
+    /// Offset of the next page, or `None` if this was the last one.
+    pub next_offset: Option<usize>,
+}
+
+/// Emitted as `room:member` whenever someone's membership, display name or avatar changes, so the
+/// member list can be patched instead of reloaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomMemberChangePayload {
+    pub room_id: String,
+    pub user_id: String,
+    pub membership: String,
+    pub display_name: Option<String>,
+    /// `echelon-media://` URL of the avatar thumbnail, see [`crate::media::cache::MediaCache`]
+    pub avatar_url: Option<String>,
+}
@@ -196,6 +196,21 @@ impl SecretService {
             .transpose()
     }
 
+    /// Delete everything stored for `user_id`: the stronghold snapshot holding the session and
+    /// sqlite password, and the keyring entry that encrypts it.
+    ///
+    /// # Arguments
+    /// * `user_id` - The user ID whose secrets should be removed.
+    pub fn remove_user(&self, user_id: &str) -> Result<()> {
+        let snapshot = self.stronghold_path.join(Self::user_id_hash(user_id));
+        match std::fs::remove_file(&snapshot) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.keyring.delete_password(&Self::user_id_hash(user_id))
+    }
+
     /// Return the sqlite password for `user_id`, generating and persisting one if it doesn't
     /// exist yet. Always returns a password (creating the stronghold snapshot if needed).
     ///
@@ -0,0 +1 @@
+pub(crate) mod account_settings;
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use anyhow::Result;
+use matrix_sdk::crypto::CollectStrategy;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+use tauri_plugin_store::StoreExt;
+use crate::ClientState;
+
+/// File (inside the app data dir) that holds the per-account settings, managed by tauri-plugin-store.
+const SETTINGS_FILE: &str = "settings.json";
+
+/// User-facing settings that apply to a single account. These are not secret, so unlike the
+/// session data they live in a plain tauri-plugin-store file keyed by user id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountSettings {
+    /// Whether read receipts are sent publicly (`m.read`) or privately (`m.read.private`).
+    pub send_public_receipts: bool,
+    /// Whether links in messages get previews fetched through the homeserver.
+    pub url_previews: bool,
+    /// Whether link previews are also fetched in encrypted rooms. Off by default, since asking the
+    /// homeserver for a preview tells it which links are being shared in the room.
+    pub url_previews_in_encrypted_rooms: bool,
+    /// Per-room overrides of the two settings above, keyed by room id.
+    pub room_url_previews: HashMap<String, bool>,
+    /// Whether room keys are shared with devices their owner hasn't verified. Applied when the
+    /// client is built, so changes take effect on the next login or session restore, see
+    /// [`SettingsSaved::restart_required`].
+    pub share_keys_with_unverified_devices: bool,
+    /// Whether sending is blocked (instead of just warned about) when a user we verified changes
+    /// their identity, until we re-verify them or accept the change. Applied when the client is
+    /// built, like the setting above.
+    pub block_on_identity_change: bool,
+    /// Rooms where sending is refused while any member has an unverified device, keyed by room id.
+    pub room_require_verified_devices: HashMap<String, bool>,
+    /// Whether invites from users we share no room with are hidden from the invites inbox.
+    pub hide_invites_from_strangers: bool,
+}
+
+/// Returned by `set_account_settings`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsSaved {
+    /// Whether the saved key sharing settings differ from the ones the running client was built
+    /// with. They only take effect once the session is restored, e.g. on the next app start.
+    pub restart_required: bool,
+}
+
+impl Default for AccountSettings {
+    fn default() -> Self {
+        AccountSettings {
+            send_public_receipts: true,
+            url_previews: true,
+            url_previews_in_encrypted_rooms: false,
+            room_url_previews: HashMap::new(),
+            share_keys_with_unverified_devices: true,
+            block_on_identity_change: false,
+            room_require_verified_devices: HashMap::new(),
+            hide_invites_from_strangers: false,
+        }
+    }
+}
+
+impl AccountSettings {
+    /// Load the settings for `user_id`, falling back to the defaults if none were saved yet.
+    ///
+    /// # Arguments
+    /// * `app_handle` - The app handle used to access the settings store.
+    /// * `user_id` - The user ID whose settings should be loaded.
+    pub fn load(app_handle: &AppHandle, user_id: &str) -> Result<Self> {
+        let store = app_handle.store(SETTINGS_FILE)?;
+        match store.get(user_id) {
+            Some(value) => Ok(serde_json::from_value(value)?),
+            None => Ok(AccountSettings::default()),
+        }
+    }
+
+    /// Whether link previews should be shown in the given room, taking the per-room override into
+    /// account before falling back to the account-wide settings.
+    ///
+    /// # Arguments
+    /// * `room_id` - The ID of the room the link was posted in.
+    /// * `is_encrypted` - Whether that room is encrypted.
+    pub fn url_previews_enabled(&self, room_id: &str, is_encrypted: bool) -> bool {
+        match self.room_url_previews.get(room_id) {
+            Some(enabled) => *enabled,
+            None if is_encrypted => self.url_previews && self.url_previews_in_encrypted_rooms,
+            None => self.url_previews,
+        }
+    }
+
+    /// The sdk strategy for picking which devices get our room keys, see
+    /// [`AccountSettings::share_keys_with_unverified_devices`] and
+    /// [`AccountSettings::block_on_identity_change`].
+    pub fn room_key_strategy(&self) -> CollectStrategy {
+        match (self.share_keys_with_unverified_devices, self.block_on_identity_change) {
+            (false, _) => CollectStrategy::OnlyTrustedDevices,
+            (true, true) => CollectStrategy::ErrorOnVerifiedUserProblem,
+            (true, false) => CollectStrategy::AllDevices,
+        }
+    }
+
+    /// Persist these settings for `user_id`.
+    ///
+    /// # Arguments
+    /// * `app_handle` - The app handle used to access the settings store.
+    /// * `user_id` - The user ID whose settings should be overwritten.
+    pub fn save(&self, app_handle: &AppHandle, user_id: &str) -> Result<()> {
+        let store = app_handle.store(SETTINGS_FILE)?;
+        store.set(user_id, serde_json::to_value(self)?);
+        store.save()?;
+        Ok(())
+    }
+
+    /// Forget the settings of `user_id`, used when the account is signed out of.
+    ///
+    /// # Arguments
+    /// * `app_handle` - The app handle used to access the settings store.
+    /// * `user_id` - The user ID whose settings should be removed.
+    pub fn remove(app_handle: &AppHandle, user_id: &str) -> Result<()> {
+        let store = app_handle.store(SETTINGS_FILE)?;
+        store.delete(user_id);
+        store.save()?;
+        Ok(())
+    }
+}
+
+/// Get the settings of the currently logged in account.
+///
+/// # Arguments
+/// * `state` - The client state containing the Matrix client whose settings should be returned.
+/// * `app_handle` - The app handle used to access the settings store.
+#[tauri::command]
+pub async fn get_account_settings(
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<AccountSettings, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let user_id = client_handler
+        .get_client()
+        .user_id()
+        .ok_or("Not logged in")?
+        .to_string();
+
+    AccountSettings::load(&app_handle, &user_id).map_err(|e| e.to_string())
+}
+
+/// Overwrite the settings of the currently logged in account. Changes to the key sharing settings
+/// are saved but not applied to the running client, the result says when that's the case.
+///
+/// # Arguments
+/// * `settings` - The new settings to persist.
+/// * `state` - The client state containing the Matrix client whose settings should be changed.
+/// * `app_handle` - The app handle used to access the settings store.
+#[tauri::command]
+pub async fn set_account_settings(
+    settings: AccountSettings,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<SettingsSaved, String> {
+    let state_r = state.0.read().await;
+    let client_handler = state_r.as_ref().unwrap();
+    let user_id = client_handler
+        .get_client()
+        .user_id()
+        .ok_or("Not logged in")?
+        .to_string();
+
+    settings.save(&app_handle, &user_id).map_err(|e| e.to_string())?;
+    Ok(SettingsSaved {
+        restart_required: settings.room_key_strategy() != *client_handler.room_key_strategy(),
+    })
+}
@@ -1,30 +1,43 @@
+use std::sync::Arc;
 use matrix_sdk::Client;
 use matrix_sdk::config::SyncSettings;
+use tauri::AppHandle;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tracing::{debug, error};
+use crate::encryption::utd::UtdTracker;
+use crate::messages::outbox::Outbox;
 
 pub struct SyncManager {
     sync_handle: RwLock<Option<JoinHandle<()>>>,
+    app_handle: AppHandle,
 }
 
 impl SyncManager {
-    pub fn new() -> Self {
+    pub fn new(app_handle: AppHandle) -> Self {
         Self {
             sync_handle: RwLock::new(None),
+            app_handle,
         }
     }
 
-    /// Start the sync loop for a given Matrix client
-    pub async fn start_sync(&self, client: Client) {
+    /// Start the sync loop for a given Matrix client. The outbox is started once the initial sync
+    /// is done and gets notified of every successful sync, so it can resume sending after an outage.
+    /// Every response goes through [`UtdTracker::on_sync_response`] for the events the sdk
+    /// couldn't decrypt.
+    pub async fn start_sync(&self, client: Client, outbox: Arc<Outbox>) {
         // Stop any existing sync first
         self.stop_sync().await;
 
         let sync_settings = SyncSettings::default();
 
         let initial_response = client.sync_once(sync_settings.clone().full_state(true)).await.expect("failed to perform initial sync");
+        UtdTracker::on_sync_response(&initial_response, &self.app_handle).await;
         let next_batch = initial_response.next_batch;
 
+        outbox.start(&client).await;
+
+        let app_handle = self.app_handle.clone();
         let handle = tokio::spawn(async move {
             debug!("Starting Matrix sync loop...");
 
@@ -34,7 +47,9 @@ impl SyncManager {
                 match client.sync_once(settings).await {
                     Ok(response) => {
                         debug!("Sync completed successfully, next batch: {}", response.next_batch);
+                        UtdTracker::on_sync_response(&response, &app_handle).await;
                         since = response.next_batch;
+                        outbox.on_sync_success(&client).await;
                     },
                     Err(e) => {
                         error!("Sync error: {:?}", e);
@@ -5,10 +5,14 @@ use ruma::{OwnedRoomId};
 use ruma::api::client::space::get_hierarchy;
 use ruma::events::direct::{OwnedDirectUserIdentifier};
 use ruma::events::{AnyGlobalAccountDataEvent, GlobalAccountDataEventType, StateEventType};
-use crate::{ClientState};
-use tauri::State;
+use crate::{ClientState, InviteState, MediaState, MemberListState, PreviewState, UtdState};
+use tauri::{AppHandle, Manager, State};
 use tracing::{debug, error, trace};
 use crate::account::account_reset_types::AccountResetType;
+use crate::account::logout_types::LogoutMode;
+use crate::client_handler::ClientHandler;
+use crate::media::cache::MediaCache;
+use crate::rooms::room_encryption::encryption_algorithm;
 use crate::rooms::room_types::{DmRoom, RawRoom, SpaceRoom};
 use crate::spaces::raw_space::{RawSpace};
 
@@ -40,7 +44,8 @@ pub async fn oauth_login(
             let client = handler.get_client().clone();
 
             // Start the sync task
-            handler.sync_manager.start_sync(client).await;
+            handler.sync_manager.start_sync(client, handler.outbox.clone()).await;
+            handler.spawn_background_tasks().await;
 
             // Now acquire write lock - read lock has been dropped
             let mut write_guard = state.0.write().await;
@@ -80,7 +85,8 @@ pub async fn oauth_register(
             let client = handler.get_client().clone();
 
             // Start the sync task
-            handler.sync_manager.start_sync(client).await;
+            handler.sync_manager.start_sync(client, handler.outbox.clone()).await;
+            handler.spawn_background_tasks().await;
 
             // Now acquire write lock - read lock has been dropped
             let mut write_guard = state.0.write().await;
@@ -128,7 +134,8 @@ pub async fn register(
     let client = handler.get_client().clone();
 
     // Start the sync task
-    handler.sync_manager.start_sync(client).await;
+    handler.sync_manager.start_sync(client, handler.outbox.clone()).await;
+    handler.spawn_background_tasks().await;
 
     // Now acquire write lock - read lock has been dropped
     let mut write_guard = state.0.write().await;
@@ -143,12 +150,14 @@ pub async fn register(
 /// * `username` - The username of the account to log in to.
 /// * `password` - The password of the account to log in to.
 /// * `homeserver` - The URL of the homeserver to log in to.
+/// * `device_name` - The display name for the new device, defaults to "Echelon (<platform>)".
 /// * `state` - The client state containing the Matrix client to perform the login on.
 #[tauri::command]
 pub async fn login(
     username: String,
     password: String,
     homeserver: String,
+    device_name: Option<String>,
     state: State<'_, ClientState>,
 ) -> Result<String, String> {
     trace!("Logging user: {} with password", username);
@@ -160,7 +169,7 @@ pub async fn login(
     let result = {
         let state_r = state.0.read().await;
         let client_handler = state_r.as_ref().unwrap();
-        client_handler.login(username, password, homeserver).await
+        client_handler.login(username, password, homeserver, device_name).await
     }; // Read lock is dropped here
 
     match result {
@@ -169,7 +178,8 @@ pub async fn login(
             let client = handler.get_client().clone();
 
             // Start the sync task
-            handler.sync_manager.start_sync(client).await;
+            handler.sync_manager.start_sync(client, handler.outbox.clone()).await;
+            handler.spawn_background_tasks().await;
 
             // Now acquire write lock - read lock has been dropped
             let mut write_guard = state.0.write().await;
@@ -182,25 +192,88 @@ pub async fn login(
     }
 }
 
-#[tauri::command]
-pub async fn logout(
-    state: State<'_, ClientState>,
-) -> Result<String, String> {
-    debug!("Logging out user...");
-
-    // Stop the sync task first
+/// Stop syncing, the outbox and the background tasks, and swap the current handler for a fresh one,
+/// so the next login has something to log in with. Returns the old handler, whose client is closed
+/// once it's dropped since nothing else holds on to it anymore. Per-account caches are cleared too.
+///
+/// # Arguments
+/// * `state` - The client state holding the handler to replace.
+/// * `app_handle` - The app handle used to reach the caches and create the fresh handler.
+pub(crate) async fn end_session(state: &ClientState, app_handle: &AppHandle) -> Option<ClientHandler> {
     {
         let state_r = state.0.read().await;
         if let Some(handler) = state_r.as_ref() {
             handler.sync_manager.stop_sync().await;
+            handler.outbox.stop().await;
+            handler.stop_background_tasks().await;
         }
     }
 
-    // Clear the client state
-    let mut write_guard = state.0.write().await;
-    *write_guard = None;
+    app_handle.state::<MemberListState>().0.clear().await;
+    app_handle.state::<InviteState>().0.clear().await;
+    app_handle.state::<UtdState>().0.clear().await;
+    app_handle.state::<PreviewState>().0.clear().await;
+    app_handle.state::<MediaState>().0.forget_sources().await;
+
+    let fresh_handler = ClientHandler::new(app_handle.clone()).await;
+    state.0.write().await.replace(fresh_handler)
+}
+
+/// Delete everything stored on this device for `user_id`, see [`ClientHandler::remove_local_data`].
+/// The media cache is shared between accounts, but it may hold decrypted media of this one, so it
+/// is cleared as well.
+///
+/// # Arguments
+/// * `app_handle` - The app handle used to access the stores.
+/// * `user_id` - The user ID whose data should be removed.
+pub(crate) async fn wipe_account(app_handle: &AppHandle, user_id: &str) -> anyhow::Result<()> {
+    ClientHandler::remove_local_data(app_handle, user_id)?;
+    if let Err(e) = app_handle.state::<MediaState>().0.clear().await {
+        error!("Failed to clear the media cache: {}", e);
+    }
+    Ok(())
+}
+
+/// Log out of the current account. Locking only drops the client from memory, so the session can
+/// be restored later. Signing out also invalidates the session on the server and deletes
+/// everything stored for the account on this device.
+///
+/// # Arguments
+/// * `mode` - Whether to sign out or lock, defaults to locking.
+/// * `state` - The client state containing the Matrix client to log out.
+/// * `app_handle` - The app handle used to access the stores when signing out.
+#[tauri::command]
+pub async fn logout(
+    mode: Option<LogoutMode>,
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
+    let mode = mode.unwrap_or_default();
+    debug!("Logging out user ({:?})...", mode);
 
-    Ok("logged out".into())
+    let old_handler = end_session(&state, &app_handle).await;
+    if mode == LogoutMode::Lock {
+        return Ok("locked".into());
+    }
+    let Some(old_handler) = old_handler else {
+        return Ok("logged out".into());
+    };
+    let Some(user_id) = old_handler.get_client().user_id().map(|u| u.to_string()) else {
+        return Ok("logged out".into());
+    };
+
+    // an unreachable server shouldn't stop us from wiping the account locally
+    if let Err(e) = old_handler.get_client().logout().await {
+        error!("Failed to invalidate the session on the server: {}", e);
+    }
+    // close the sqlite store before deleting it
+    drop(old_handler);
+
+    wipe_account(&app_handle, &user_id)
+        .await
+        .map_err(|e| format!("Signed out, but failed to delete local data: {}", e))?;
+
+    Ok("signed out".into())
 }
 
 /// Restore a previous session for the given username and homeserver. This will attempt to load the session
@@ -235,7 +308,8 @@ pub async fn restore_session(
             let client = handler.get_client().clone();
 
             // Start the sync task
-            handler.sync_manager.start_sync(client).await;
+            handler.sync_manager.start_sync(client, handler.outbox.clone()).await;
+            handler.spawn_background_tasks().await;
 
             // Now acquire write lock - read lock has been dropped
             let mut write_guard = state.0.write().await;
@@ -249,32 +323,45 @@ pub async fn restore_session(
 }
 
 
-/// Reset the account based on the specified reset type and provided credentials or backup key.
-/// This function handles different types of account resets, such as password reset or complete
-/// account wipe, depending on the `AccountResetType` provided.
+/// Reset the account based on the specified reset type and provided credentials.
+/// This function handles different types of account resets, such as a cross-signing identity reset
+/// or a key backup reset, depending on the `AccountResetType` provided. To recover with an existing
+/// recovery key instead, use [`crate::encryption::recovery::recover_with_key`].
 ///
 /// # Arguments
 /// * `account_reset_type` - The type of account reset to perform, defined by the
-///   `AccountResetType` enum, which specifies the reset method (e.g., password reset, key backup, etc.).
+///   `AccountResetType` enum, which specifies the reset method (e.g., identity reset, key backup, etc.).
 /// * `password` - An optional password, required for identity reset
-/// * `key_backup` - An optional key backup, required for key backup reset
 /// * `state` - The client state containing the Matrix client to perform the reset operation on
+/// * `app_handle` - The app handle used to open the approval page of an OAuth identity reset
+///
+/// ### Returns
+/// The new recovery key if the reset created one, which the user has to save.
 #[tauri::command]
 pub async fn reset_account(
     account_reset_type: AccountResetType,
     password: Option<String>,
-    key_backup: Option<String>,
-    state: State<'_, ClientState>
-) -> Result<String, String> {
-    // Call reset_account in a separate scope to drop the read lock
-    let result = {
+    state: State<'_, ClientState>,
+    app_handle: AppHandle,
+) -> Result<Option<String>, String> {
+    // Clone what the reset needs in a separate scope to drop the read lock, approving an OAuth
+    // identity reset can take a while
+    let (client, identity_reset) = {
         let state_r = state.0.read().await;
         let client_handler = state_r.as_ref().unwrap();
-        client_handler.reset_account(account_reset_type, password, key_backup).await
+        (client_handler.get_client().clone(), client_handler.identity_reset_slot())
     }; // Read lock is dropped here
 
+    let result = ClientHandler::reset_account(
+        client,
+        app_handle,
+        identity_reset,
+        account_reset_type,
+        password,
+    ).await;
+
     match result {
-        Ok(_) => Ok("account reset successful".into()),
+        Ok(recovery_key) => Ok(recovery_key),
         Err(e) => Err(format!("Account reset failed: {}", e))
     }
 }
@@ -299,7 +386,7 @@ pub async fn get_spaces(
             let room_id = room.room_id().to_string();
             let name = room.name();
             let topic = room.topic();
-            let avatar_url = room.avatar_url().map(|m| m.to_string());
+            let avatar_url = room.avatar_url().map(|m| MediaCache::avatar_url(&m));
             SpaceRoom {
                 base: RawRoom {
                     id: room_id,
@@ -307,6 +394,8 @@ pub async fn get_spaces(
                     topic,
                     avatar_url,
                     is_space: room.is_space(),
+                    is_encrypted: room.encryption_state().is_encrypted(),
+                    encryption_algorithm: encryption_algorithm(&room),
                 },
                 parent_spaces: Vec::new(), // Root spaces have no parents
             }
@@ -334,7 +423,7 @@ pub async fn get_rooms(
             let room_id = room.room_id().to_string();
             let name = room.name();
             let topic = room.topic();
-            let avatar_url = room.avatar_url().map(|m| m.to_string());
+            let avatar_url = room.avatar_url().map(|m| MediaCache::avatar_url(&m));
 
             room_infos.push(
                 RawRoom {
@@ -343,6 +432,8 @@ pub async fn get_rooms(
                     topic,
                     avatar_url,
                     is_space: room.is_space(),
+                    is_encrypted: room.encryption_state().is_encrypted(),
+                    encryption_algorithm: encryption_algorithm(&room),
                 }
             )
         }
@@ -388,8 +479,10 @@ pub async fn get_all_spaces_with_trees(
                             id: space_id,
                             name: space.name(),
                             topic: space.topic(),
-                            avatar_url: space.avatar_url().map(|m| m.to_string()),
+                            avatar_url: space.avatar_url().map(|m| MediaCache::avatar_url(&m)),
                             is_space: space.is_space(),
+                            is_encrypted: space.encryption_state().is_encrypted(),
+                            encryption_algorithm: encryption_algorithm(&space),
                         },
                         rooms: tree,
                     })
@@ -468,7 +561,13 @@ pub async fn get_space_tree(
         let name = room_summary.summary.name.clone();
         let is_space = client.get_room(&*room_summary.summary.room_id).map(|r| r.is_space()).unwrap_or(false);
         let topic = room_summary.summary.topic.clone();
-        let avatar_url = room_summary.summary.avatar_url.as_ref().map(|u| u.to_string());
+        let avatar_url = room_summary.summary.avatar_url.as_deref().map(MediaCache::avatar_url);
+        // rooms we aren't in only have the summary to go by
+        let encryption_algorithm = match client.get_room(&*room_summary.summary.room_id) {
+            Some(room) => encryption_algorithm(&room),
+            None => room_summary.summary.encryption.as_ref().map(|a| a.to_string()),
+        };
+        let is_encrypted = encryption_algorithm.is_some();
 
         id_to_name.insert(room_id.clone(), name.clone().unwrap_or_else(|| "Unnamed".to_string()));
 
@@ -482,7 +581,15 @@ pub async fn get_space_tree(
 
         debug!("  Child: {:?} ({})", name, room_id);
 
-        raw_rooms.push(RawRoom { id: room_id, name, topic, avatar_url, is_space });
+        raw_rooms.push(RawRoom {
+            id: room_id,
+            name,
+            topic,
+            avatar_url,
+            is_space,
+            is_encrypted,
+            encryption_algorithm,
+        });
     }
 
     // we use this to build the parent path for each room, we look up the parent of the room in the
@@ -516,6 +623,8 @@ pub async fn get_space_tree(
                 topic: raw.topic,
                 avatar_url: raw.avatar_url,
                 is_space: raw.is_space,
+                is_encrypted: raw.is_encrypted,
+                encryption_algorithm: raw.encryption_algorithm,
             },
             parent_spaces,
         });
@@ -566,7 +675,7 @@ pub async fn get_dm_rooms(
                        if let Some(room) = client.get_room(&room_id) {
                            let name = room.name();
                            let topic = room.topic();
-                           let avatar_url = room.avatar_url().map(|u| u.to_string());
+                           let avatar_url = room.avatar_url().map(|u| MediaCache::avatar_url(&u));
                            dm_rooms.push(DmRoom {
                                base: RawRoom {
                                    id: room_id.to_string(),
@@ -574,6 +683,8 @@ pub async fn get_dm_rooms(
                                    topic,
                                    avatar_url,
                                    is_space: false,
+                                   is_encrypted: room.encryption_state().is_encrypted(),
+                                   encryption_algorithm: encryption_algorithm(&room),
                                },
                                members: user_ids.into_iter().map(|u| u.to_string()).collect(),
                            });
@@ -631,16 +742,25 @@ async fn get_orphaned_rooms(client: &Client) -> Result<Vec<DmRoom>, String> {
             let room_id = room.room_id().to_string();
             let name = room.name();
             let topic = room.topic();
-            let avatar_url = room.avatar_url().map(|u| u.to_string());
+            let avatar_url = room.avatar_url().map(|u| MediaCache::avatar_url(&u));
+            // user ids like the m.direct rooms above, names come from get_room_members
             let members = room
-                .members(RoomMemberships::all())
+                .members(RoomMemberships::ACTIVE)
                 .await
                 .unwrap_or_default()
                 .iter()
-                .filter_map(|u| u.display_name().map(|n| n.to_string()))
+                .map(|u| u.user_id().to_string())
                 .collect();
             Some(DmRoom {
-                base: RawRoom { id: room_id, name, topic, avatar_url, is_space: false },
+                base: RawRoom {
+                    id: room_id,
+                    name,
+                    topic,
+                    avatar_url,
+                    is_space: false,
+                    is_encrypted: room.encryption_state().is_encrypted(),
+                    encryption_algorithm: encryption_algorithm(&room),
+                },
                 members,
             })
         });